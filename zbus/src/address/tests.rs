@@ -79,6 +79,16 @@ fn parse_unix() {
         "Invalid value for key: `runtime`"
     );
     Address::try_from(String::from("unix:path=/tmp/foo")).unwrap();
+
+    let addr = Address::try_from("unix:path=/tmp/dbus%20foo%2c%3dbar%25").unwrap();
+    let Transport::Unix(u) = addr.transport().unwrap() else {
+        panic!();
+    };
+    assert_eq!(
+        u.kind(),
+        &UnixAddrKind::Path(Cow::Borrowed(OsStr::new("/tmp/dbus foo,=bar%")))
+    );
+    assert_eq!(addr.to_string(), "unix:path=/tmp/dbus%20foo%2C%3Dbar%25");
 }
 
 #[cfg(target_os = "macos")]
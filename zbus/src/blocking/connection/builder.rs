@@ -63,6 +63,14 @@ impl<'a> Builder<'a> {
         Self(crate::connection::Builder::unix_stream(stream))
     }
 
+    /// Create a builder for a connection that will use the given file descriptor.
+    ///
+    /// See [`crate::connection::Builder::unix_fd`] for details.
+    #[cfg(unix)]
+    pub fn unix_fd(fd: std::os::fd::OwnedFd) -> Self {
+        Self(crate::connection::Builder::unix_fd(fd))
+    }
+
     /// Create a builder for a connection that will use the given TCP stream.
     ///
     /// If the default `async-io` feature is disabled, this method will expect a
@@ -95,6 +103,44 @@ impl<'a> Builder<'a> {
         Self(self.0.auth_mechanism(auth_mechanism))
     }
 
+    /// Specify the authorization identity to send with the `EXTERNAL` mechanism.
+    ///
+    /// See [`crate::connection::Builder::external_identity`] for details.
+    pub fn external_identity<I>(self, id: I) -> Self
+    where
+        I: Into<crate::conn::ExternalIdentity>,
+    {
+        Self(self.0.external_identity(id))
+    }
+
+    /// Set a timeout for the authentication handshake.
+    ///
+    /// See [`crate::connection::Builder::handshake_timeout`] for details.
+    pub fn handshake_timeout(self, timeout: std::time::Duration) -> Self {
+        Self(self.0.handshake_timeout(timeout))
+    }
+
+    /// Tolerate non-standard server replies during UNIX FD negotiation.
+    ///
+    /// See [`crate::connection::Builder::lenient_fd_negotiation`] for details.
+    pub fn lenient_fd_negotiation(self) -> Self {
+        Self(self.0.lenient_fd_negotiation())
+    }
+
+    /// Accept handshake lines terminated by a lone `\n`.
+    ///
+    /// See [`crate::connection::Builder::lenient_line_endings`] for details.
+    pub fn lenient_line_endings(self) -> Self {
+        Self(self.0.lenient_line_endings())
+    }
+
+    /// Record the lines exchanged during the authentication handshake.
+    ///
+    /// See [`crate::connection::Builder::handshake_transcript`] for details.
+    pub fn handshake_transcript(self, mode: crate::connection::TranscriptMode) -> Self {
+        Self(self.0.handshake_transcript(mode))
+    }
+
     /// The to-be-created connection will be a peer-to-peer connection.
     ///
     /// This method is only available when the `p2p` feature is enabled.
@@ -122,6 +168,38 @@ impl<'a> Builder<'a> {
         self.0.server(guid).map(Self)
     }
 
+    /// Set what to do when the credentials of the client can't be obtained from the socket.
+    ///
+    /// See [`crate::connection::Builder::credential_policy`] for details.
+    #[cfg(feature = "p2p")]
+    pub fn credential_policy(self, policy: crate::connection::CredentialPolicy) -> Self {
+        Self(self.0.credential_policy(policy))
+    }
+
+    /// Accept any identity claimed by the client, without verifying it.
+    ///
+    /// See [`crate::connection::Builder::insecure_skip_identity_check`] for details.
+    #[cfg(feature = "p2p")]
+    pub fn insecure_skip_identity_check(self) -> Self {
+        Self(self.0.insecure_skip_identity_check())
+    }
+
+    /// Tolerate clients that send more than one NUL byte before their first command.
+    ///
+    /// See [`crate::connection::Builder::lenient_leading_nul`] for details.
+    #[cfg(feature = "p2p")]
+    pub fn lenient_leading_nul(self) -> Self {
+        Self(self.0.lenient_leading_nul())
+    }
+
+    /// Authenticate the client against the given credentials.
+    ///
+    /// See [`crate::connection::Builder::peer_credentials`] for details.
+    #[cfg(feature = "p2p")]
+    pub fn peer_credentials(self, credentials: crate::fdo::ConnectionCredentials) -> Self {
+        Self(self.0.peer_credentials(credentials))
+    }
+
     /// Set the capacity of the main (unfiltered) queue.
     ///
     /// Since typically you'd want to set this at instantiation time, you can set it through the
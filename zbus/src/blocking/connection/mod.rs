@@ -55,6 +55,29 @@ impl Connection {
         self.inner.server_guid()
     }
 
+    /// The maximum number of file descriptors that can be sent in a single message.
+    ///
+    /// See [`crate::Connection::max_fds_per_message`] for details.
+    #[cfg(unix)]
+    pub fn max_fds_per_message(&self) -> usize {
+        self.inner.max_fds_per_message()
+    }
+
+    /// Whether file descriptor passing was requested during the handshake.
+    ///
+    /// See [`crate::Connection::unix_fd_requested`] for details.
+    #[cfg(unix)]
+    pub fn unix_fd_requested(&self) -> bool {
+        self.inner.unix_fd_requested()
+    }
+
+    /// Take the transcript of the authentication handshake.
+    ///
+    /// See [`crate::Connection::take_handshake_transcript`] for details.
+    pub fn take_handshake_transcript(&self) -> Vec<crate::conn::TranscriptEntry> {
+        self.inner.take_handshake_transcript()
+    }
+
     /// The unique name as assigned by the message bus or `None` if not a message bus connection.
     pub fn unique_name(&self) -> Option<&OwnedUniqueName> {
         self.inner.unique_name()
@@ -260,7 +283,9 @@ impl Connection {
     ///
     /// # Caveats
     ///
-    /// Currently `unix_group_ids` and `linux_security_label` fields are not populated.
+    /// Currently the `unix_group_ids` field is not populated and `linux_security_label` is only
+    /// populated on Linux, for Unix sockets, when a Linux Security Module (e.g. SELinux or
+    /// AppArmor) is enabled.
     pub fn peer_credentials(&self) -> io::Result<ConnectionCredentials> {
         block_on(self.inner.peer_credentials())
     }
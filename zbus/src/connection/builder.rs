@@ -4,10 +4,13 @@ use event_listener::Event;
 use static_assertions::assert_impl_all;
 #[cfg(not(feature = "tokio"))]
 use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
 #[cfg(all(unix, not(feature = "tokio")))]
 use std::os::unix::net::UnixStream;
 use std::{
     collections::{HashMap, HashSet},
+    time::Duration,
     vec,
 };
 #[cfg(feature = "tokio")]
@@ -21,6 +24,8 @@ use uds_windows::UnixStream;
 #[cfg(all(feature = "vsock", not(feature = "tokio")))]
 use vsock::VsockStream;
 
+#[cfg(feature = "p2p")]
+use tracing::debug;
 use zvariant::ObjectPath;
 
 use crate::{
@@ -30,9 +35,11 @@ use crate::{
     Connection, Error, Executor, Guid, OwnedGuid, Result,
 };
 
+#[cfg(feature = "p2p")]
+use super::handshake::CredentialPolicy;
 use super::{
     connect::connect_address,
-    handshake::{AuthMechanism, Authenticated},
+    handshake::{AuthMechanism, Authenticated, ExternalIdentity, HandshakeOptions, TranscriptMode},
     socket::{BoxedSplit, ReadHalf, Split, WriteHalf},
 };
 
@@ -42,6 +49,8 @@ const DEFAULT_MAX_QUEUED: usize = 64;
 enum Target {
     #[cfg(any(unix, not(feature = "tokio")))]
     UnixStream(UnixStream),
+    #[cfg(unix)]
+    UnixFd(OwnedFd),
     TcpStream(TcpStream),
     #[cfg(any(
         all(feature = "vsock", not(feature = "tokio")),
@@ -69,6 +78,11 @@ pub struct Builder<'a> {
     interfaces: Interfaces<'a>,
     names: HashSet<WellKnownName<'a>>,
     auth_mechanism: Option<AuthMechanism>,
+    external_id: Option<ExternalIdentity>,
+    handshake_options: HandshakeOptions,
+    handshake_timeout: Option<Duration>,
+    #[cfg(feature = "p2p")]
+    peer_credentials: Option<crate::fdo::ConnectionCredentials>,
     #[cfg(feature = "bus-impl")]
     unique_name: Option<crate::names::UniqueName<'a>>,
 }
@@ -145,6 +159,39 @@ impl<'a> Builder<'a> {
         Self::new(Target::UnixStream(stream))
     }
 
+    /// Create a builder for a connection that will use the given file descriptor.
+    ///
+    /// This is useful when a launcher or service manager hands the process an already connected
+    /// D-Bus socket. The file descriptor must refer to a connected unix stream socket. The
+    /// connection takes ownership of it and will close it when dropped. Building the connection
+    /// fails with [`Error::Handshake`] if it's not a stream socket.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::error::Error;
+    /// # use std::os::fd::{FromRawFd, OwnedFd};
+    /// # use zbus::connection::Builder;
+    /// # use zbus::block_on;
+    /// #
+    /// # block_on(async {
+    /// // SAFETY: Our launcher guarantees that file descriptor `3` is a connected socket that is
+    /// // owned by us and not used anywhere else.
+    /// let fd = unsafe { OwnedFd::from_raw_fd(3) };
+    /// let conn = Builder::unix_fd(fd).build().await?;
+    ///
+    /// // Do something useful with `conn`..
+    /// #     drop(conn);
+    /// #     Ok::<(), zbus::Error>(())
+    /// # }).unwrap();
+    /// #
+    /// # Ok::<_, Box<dyn Error + Send + Sync>>(())
+    /// ```
+    #[cfg(unix)]
+    pub fn unix_fd(fd: OwnedFd) -> Self {
+        Self::new(Target::UnixFd(fd))
+    }
+
     /// Create a builder for a connection that will use the given TCP stream.
     ///
     /// If the default `async-io` feature is disabled, this method will expect a
@@ -195,6 +242,69 @@ impl<'a> Builder<'a> {
         self
     }
 
+    /// Specify the authorization identity to send with the `EXTERNAL` mechanism.
+    ///
+    /// By default, the effective UID of the current process (or its SID on Windows) is sent. See
+    /// [`ExternalIdentity`] for the alternatives.
+    pub fn external_identity<I>(mut self, id: I) -> Self
+    where
+        I: Into<ExternalIdentity>,
+    {
+        self.external_id = Some(id.into());
+
+        self
+    }
+
+    /// Set a timeout for the authentication handshake.
+    ///
+    /// If the handshake (including the `Hello` call for bus connections) doesn't complete in time,
+    /// building the connection fails with [`Error::Handshake`]. By default, there is no timeout.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+
+        self
+    }
+
+    /// Tolerate non-standard server replies during UNIX FD negotiation.
+    ///
+    /// Some server implementations send a `DATA` command before replying to `NEGOTIATE_UNIX_FD`,
+    /// or reply with something else than `AGREE_UNIX_FD` or `ERROR`, which makes the handshake fail
+    /// by default. With this option, each such `DATA` is answered with an empty `DATA`, any other
+    /// unexpected reply is taken as FD passing being declined, and a warning is logged.
+    ///
+    /// This also means `BEGIN` is only sent once the server replied to `NEGOTIATE_UNIX_FD`, which
+    /// costs an extra round trip.
+    pub fn lenient_fd_negotiation(mut self) -> Self {
+        self.handshake_options.lenient_fd_negotiation = true;
+
+        self
+    }
+
+    /// Accept handshake lines terminated by a lone `\n`.
+    ///
+    /// The specification requires `\r\n` line endings and the handshake fails on anything else
+    /// by default. This option allows interoperating with legacy peers that only send `\n`.
+    pub fn lenient_line_endings(mut self) -> Self {
+        self.handshake_options.lenient_line_endings = true;
+
+        self
+    }
+
+    /// Record the lines exchanged during the authentication handshake.
+    ///
+    /// This is mainly useful for auditing. Each line is recorded along with its direction and the
+    /// time it was sent or received. With [`TranscriptMode::Redacted`], the authentication data
+    /// (e.g the identity sent with `EXTERNAL`) is replaced with `[REDACTED]`.
+    ///
+    /// Use [`Connection::take_handshake_transcript`] to retrieve the transcript once the
+    /// connection is established. Note that nothing is recorded for connections created with
+    /// [`Builder::authenticated_socket`].
+    pub fn handshake_transcript(mut self, mode: TranscriptMode) -> Self {
+        self.handshake_options.transcript = Some(mode);
+
+        self
+    }
+
     /// The to-be-created connection will be a peer-to-peer connection.
     ///
     /// This method is only available when the `p2p` feature is enabled.
@@ -226,6 +336,70 @@ impl<'a> Builder<'a> {
         Ok(self)
     }
 
+    /// Authenticate the client against the given credentials.
+    ///
+    /// By default, a server queries the socket for the credentials of the client. This allows
+    /// reusing the credentials of an already authenticated connection (see
+    /// [`crate::Connection::peer_credentials`]) instead, e.g. for auxiliary connections opened on
+    /// behalf of the same peer, or for sockets that can't provide the peer credentials.
+    ///
+    /// This method is only available when the `p2p` feature is enabled and only has an effect on
+    /// server connections (see [`Builder::server`]).
+    #[cfg(feature = "p2p")]
+    pub fn peer_credentials(mut self, credentials: crate::fdo::ConnectionCredentials) -> Self {
+        self.peer_credentials = Some(credentials);
+
+        self
+    }
+
+    /// Set what to do when the credentials of the client can't be obtained from the socket.
+    ///
+    /// This only affects the `EXTERNAL` mechanism. By default, the handshake fails
+    /// ([`CredentialPolicy::Required`]). See [`CredentialPolicy`] for the alternatives.
+    ///
+    /// This method is only available when the `p2p` feature is enabled and only has an effect on
+    /// server connections (see [`Builder::server`]).
+    #[cfg(feature = "p2p")]
+    pub fn credential_policy(mut self, policy: CredentialPolicy) -> Self {
+        self.handshake_options.credential_policy = policy;
+
+        self
+    }
+
+    /// Accept any identity claimed by the client, without verifying it.
+    ///
+    /// With this option, the `EXTERNAL` mechanism succeeds whatever identity the client claims,
+    /// even if it doesn't match the credentials obtained from the socket, and even if those can't
+    /// be obtained at all.
+    ///
+    /// This is **insecure** and only meant for peer-to-peer connections between processes that
+    /// already trust each other, e.g. over a socket pair created by one of them. For clients that
+    /// don't authenticate at all, consider the `ANONYMOUS` mechanism instead (see
+    /// [`Builder::auth_mechanism`]).
+    ///
+    /// This method is only available when the `p2p` feature is enabled and only has an effect on
+    /// server connections (see [`Builder::server`]).
+    #[cfg(feature = "p2p")]
+    pub fn insecure_skip_identity_check(mut self) -> Self {
+        self.handshake_options.skip_identity_check = true;
+
+        self
+    }
+
+    /// Tolerate clients that send more than one NUL byte before their first command.
+    ///
+    /// The client is supposed to send a single NUL byte at the start of the handshake. With this
+    /// option, any extra NUL bytes are skipped with a warning instead of failing the handshake.
+    ///
+    /// This method is only available when the `p2p` feature is enabled and only has an effect on
+    /// server connections (see [`Builder::server`]).
+    #[cfg(feature = "p2p")]
+    pub fn lenient_leading_nul(mut self) -> Self {
+        self.handshake_options.lenient_leading_nul = true;
+
+        self
+    }
+
     /// Set the capacity of the main (unfiltered) queue.
     ///
     /// Since typically you'd want to set this at instantiation time, you can set it through the
@@ -364,9 +538,14 @@ impl<'a> Builder<'a> {
         let (mut stream, server_guid, authenticated) = self.target_connect().await?;
         let mut auth = if authenticated {
             let (socket_read, socket_write) = stream.take();
+            #[cfg(unix)]
+            let cap_unix_fd = socket_read.can_pass_unix_fd();
             Authenticated {
                 #[cfg(unix)]
-                cap_unix_fd: socket_read.can_pass_unix_fd(),
+                cap_unix_fd,
+                // There's no handshake, so consider FD passing requested whenever it's possible.
+                #[cfg(unix)]
+                unix_fd_requested: cap_unix_fd,
                 socket_read: Some(socket_read),
                 socket_write,
                 // SAFETY: `server_guid` is provided as arg of `Builder::authenticated_socket`.
@@ -375,47 +554,90 @@ impl<'a> Builder<'a> {
                 unique_name,
                 #[cfg(unix)]
                 already_received_fds: vec![],
+                transcript: vec![],
             }
         } else {
-            #[cfg(feature = "p2p")]
-            match self.guid {
-                None => {
-                    // SASL Handshake
-                    Authenticated::client(stream, server_guid, self.auth_mechanism, is_bus_conn)
+            let handshake = async {
+                #[cfg(feature = "p2p")]
+                let auth = match self.guid.take() {
+                    None => {
+                        // SASL Handshake
+                        Authenticated::client(
+                            stream,
+                            server_guid,
+                            self.auth_mechanism,
+                            self.external_id.take(),
+                            is_bus_conn,
+                            self.handshake_options,
+                        )
                         .await?
-                }
-                Some(guid) => {
-                    if !self.p2p {
-                        return Err(Error::Unsupported);
                     }
-
-                    let creds = stream.read_mut().peer_credentials().await?;
-                    #[cfg(unix)]
-                    let client_uid = creds.unix_user_id();
-                    #[cfg(windows)]
-                    let client_sid = creds.into_windows_sid();
-
-                    Authenticated::server(
-                        stream,
-                        guid.to_owned().into(),
+                    Some(guid) => {
+                        if !self.p2p {
+                            return Err(Error::Unsupported);
+                        }
+
+                        let creds = match self.peer_credentials.take() {
+                            Some(creds) => creds,
+                            None => match stream.read_mut().peer_credentials().await {
+                                Ok(creds) => creds,
+                                Err(e)
+                                    if self.handshake_options.credential_policy
+                                        != CredentialPolicy::Required
+                                        || self.handshake_options.skip_identity_check =>
+                                {
+                                    debug!("Failed to get the peer credentials: {e}");
+
+                                    Default::default()
+                                }
+                                Err(e) => return Err(e.into()),
+                            },
+                        };
                         #[cfg(unix)]
-                        client_uid,
+                        let client_uid = creds.unix_user_id();
                         #[cfg(windows)]
-                        client_sid,
-                        self.auth_mechanism,
-                        unique_name,
-                    )
-                    .await?
-                }
+                        let client_sid = creds.into_windows_sid();
+
+                        Authenticated::server(
+                            stream,
+                            guid.to_owned().into(),
+                            #[cfg(unix)]
+                            client_uid,
+                            #[cfg(windows)]
+                            client_sid,
+                            self.auth_mechanism,
+                            unique_name,
+                            self.handshake_options,
+                        )
+                        .await?
+                    }
+                };
+
+                #[cfg(not(feature = "p2p"))]
+                let auth = Authenticated::client(
+                    stream,
+                    server_guid,
+                    self.auth_mechanism,
+                    self.external_id.take(),
+                    is_bus_conn,
+                    self.handshake_options,
+                )
+                .await?;
+
+                Ok::<_, Error>(auth)
+            };
+
+            match self.handshake_timeout {
+                Some(timeout) => crate::utils::timeout(handshake, timeout)
+                    .await
+                    .ok_or_else(|| Error::Handshake("Timed out".into()))??,
+                None => handshake.await?,
             }
-
-            #[cfg(not(feature = "p2p"))]
-            Authenticated::client(stream, server_guid, self.auth_mechanism, is_bus_conn).await?
         };
 
         // SAFETY: `Authenticated` is always built with these fields set to `Some`.
         let socket_read = auth.socket_read.take().unwrap();
-        let already_received_bytes = auth.already_received_bytes.drain(..).collect();
+        let already_received_bytes = auth.take_leftover();
         #[cfg(unix)]
         let already_received_fds = auth.already_received_fds.drain(..).collect();
 
@@ -468,6 +690,11 @@ impl<'a> Builder<'a> {
             interfaces: HashMap::new(),
             names: HashSet::new(),
             auth_mechanism: None,
+            external_id: None,
+            handshake_options: HandshakeOptions::default(),
+            handshake_timeout: None,
+            #[cfg(feature = "p2p")]
+            peer_credentials: None,
             #[cfg(feature = "bus-impl")]
             unique_name: None,
         }
@@ -483,6 +710,27 @@ impl<'a> Builder<'a> {
             Target::UnixStream(stream) => Async::new(stream)?.into(),
             #[cfg(all(unix, feature = "tokio"))]
             Target::UnixStream(stream) => stream.into(),
+            #[cfg(unix)]
+            Target::UnixFd(fd) => {
+                use nix::sys::socket::{getsockopt, sockopt::SockType, SockType as Type};
+
+                // The handshake relies on a reliable, ordered byte stream.
+                if getsockopt(&fd, SockType)? != Type::Stream {
+                    return Err(Error::Handshake("socket is not a stream socket".into()));
+                }
+                let stream = std::os::unix::net::UnixStream::from(fd);
+                stream.set_nonblocking(true)?;
+
+                #[cfg(not(feature = "tokio"))]
+                {
+                    Async::new(stream)?.into()
+                }
+
+                #[cfg(feature = "tokio")]
+                {
+                    UnixStream::from_std(stream)?.into()
+                }
+            }
             #[cfg(not(feature = "tokio"))]
             Target::TcpStream(stream) => Async::new(stream)?.into(),
             #[cfg(feature = "tokio")]
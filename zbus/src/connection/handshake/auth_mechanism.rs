@@ -49,7 +49,8 @@ impl FromStr for AuthMechanism {
         match s {
             "EXTERNAL" => Ok(AuthMechanism::External),
             "ANONYMOUS" => Ok(AuthMechanism::Anonymous),
-            _ => Err(Error::Handshake(format!("Unsupported mechanism: {s}"))),
+            // The name isn't included since it may be a malformed line with credentials.
+            _ => Err(Error::Handshake("Unsupported mechanism".into())),
         }
     }
 }
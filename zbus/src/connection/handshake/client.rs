@@ -1,11 +1,11 @@
 use async_trait::async_trait;
-use tracing::{instrument, trace, warn};
+use tracing::{debug, instrument, trace, warn};
 
 use crate::{conn::socket::ReadHalf, is_flatpak, names::OwnedUniqueName, Message};
 
 use super::{
-    sasl_auth_id, AuthMechanism, Authenticated, BoxedSplit, Command, Common, Error, Handshake,
-    OwnedGuid, Result,
+    step_error, AuthMechanism, Authenticated, BoxedSplit, Command, Common, Error, ExternalIdentity,
+    Handshake, OwnedGuid, Result, TranscriptMode,
 };
 
 /// A representation of an in-progress handshake, client-side
@@ -15,8 +15,10 @@ use super::{
 #[derive(Debug)]
 pub struct Client {
     common: Common,
+    external_id: ExternalIdentity,
     server_guid: Option<OwnedGuid>,
     bus: bool,
+    lenient_fd_negotiation: bool,
 }
 
 impl Client {
@@ -24,6 +26,7 @@ impl Client {
     pub fn new(
         socket: BoxedSplit,
         mechanism: Option<AuthMechanism>,
+        external_id: Option<ExternalIdentity>,
         server_guid: Option<OwnedGuid>,
         bus: bool,
     ) -> Client {
@@ -31,17 +34,37 @@ impl Client {
 
         Client {
             common: Common::new(socket, mechanism),
+            external_id: external_id.unwrap_or_default(),
             server_guid,
             bus,
+            lenient_fd_negotiation: false,
         }
     }
 
+    /// Record all the lines exchanged during the handshake.
+    pub fn record_transcript(&mut self, mode: TranscriptMode) {
+        self.common.record_transcript(mode);
+    }
+
+    /// Tolerate non-standard replies from the server while negotiating UNIX FD passing.
+    ///
+    /// Some non-standard servers send `DATA` before replying to `NEGOTIATE_UNIX_FD`. When enabled,
+    /// each such `DATA` is answered with an empty `DATA` and any other unexpected reply is taken as
+    /// FD passing being declined, instead of failing the handshake. `BEGIN` is then only sent after
+    /// the reply to `NEGOTIATE_UNIX_FD`.
+    pub fn set_lenient_fd_negotiation(&mut self, lenient: bool) {
+        self.lenient_fd_negotiation = lenient;
+    }
+
+    /// Accept lines terminated by a lone `\n` from the server, rather than only `\r\n`.
+    pub fn set_lenient_line_endings(&mut self, lenient: bool) {
+        self.common.set_lenient_line_endings(lenient);
+    }
+
     fn set_guid(&mut self, guid: OwnedGuid) -> Result<()> {
         match &self.server_guid {
             Some(server_guid) if *server_guid != guid => {
-                return Err(Error::Handshake(format!(
-                    "Server GUID mismatch: expected {server_guid}, got {guid}",
-                )));
+                return Err(Error::GuidMismatch(server_guid.clone(), guid));
             }
             Some(_) => (),
             None => self.server_guid = Some(guid),
@@ -83,7 +106,15 @@ impl Client {
         let auth_cmd = match mechanism {
             AuthMechanism::Anonymous => Command::Auth(Some(mechanism), Some("zbus".into())),
             AuthMechanism::External => {
-                Command::Auth(Some(mechanism), Some(sasl_auth_id()?.into_bytes()))
+                // Sockets default to `EXTERNAL` only if the server can verify the claimed identity
+                // through them.
+                if self.common.socket_mut().read_mut().auth_mechanism() != AuthMechanism::External {
+                    warn!(
+                        "Using EXTERNAL authentication over a transport that can't convey \
+                         credentials, the server has to trust the claimed identity"
+                    );
+                }
+                self.external_id.auth_command()?
             }
         };
         self.common.write_command(auth_cmd).await?;
@@ -95,12 +126,10 @@ impl Client {
 
                 Ok(())
             }
-            Command::Rejected(accepted) => {
-                let list = accepted.replace(" ", ", ");
-                Err(Error::Handshake(format!(
-                    "{mechanism} rejected by the server. Accepted mechanisms: [{list}]"
-                )))
-            }
+            Command::Rejected(accepted) => Err(Error::AuthRejected(
+                mechanism,
+                accepted.split_whitespace().map(String::from).collect(),
+            )),
             Command::Error(e) => Err(Error::Handshake(format!("Received error from server: {e}"))),
             cmd => Err(Error::Handshake(format!(
                 "Unexpected command from server: {cmd}"
@@ -115,12 +144,17 @@ impl Client {
 
         let can_pass_fd = self.common.socket_mut().read_mut().can_pass_unix_fd();
         if can_pass_fd {
+            #[cfg(unix)]
+            self.common.set_unix_fd_requested();
             // xdg-dbus-proxy can't handle pipelining, hence this special handling.
             // FIXME: Remove this as soon as flatpak is fixed and fix is available in major distros.
             // See https://github.com/flatpak/xdg-dbus-proxy/issues/21
-            if is_flatpak() {
+            //
+            // In lenient mode, we may have to answer `DATA` from the server, which must happen
+            // before `BEGIN` (and `Hello`) since the server expects D-Bus messages right after.
+            if is_flatpak() || self.lenient_fd_negotiation {
                 self.common.write_command(Command::NegotiateUnixFD).await?;
-                match self.common.read_command().await? {
+                match self.read_secondary_response().await? {
                     Command::AgreeUnixFD => self.common.set_cap_unix_fd(true),
                     Command::Error(e) => warn!("UNIX file descriptor passing rejected: {e}"),
                     cmd => {
@@ -150,8 +184,8 @@ impl Client {
 
     #[instrument(skip(self))]
     async fn receive_secondary_responses(&mut self, expected_n_responses: usize) -> Result<()> {
-        for response in self.common.read_commands(expected_n_responses).await? {
-            match response {
+        for _ in 0..expected_n_responses {
+            match self.read_secondary_response().await? {
                 Command::Ok(guid) => {
                     trace!("Received OK from server");
                     self.set_guid(guid)?;
@@ -168,6 +202,32 @@ impl Client {
 
         Ok(())
     }
+
+    /// Read the next response to the commands sent after authentication.
+    async fn read_secondary_response(&mut self) -> Result<Command> {
+        loop {
+            let cmd = match self.common.try_read_command().await? {
+                Ok(cmd) => cmd,
+                // A blank or otherwise unparsable line.
+                Err(Error::Handshake(e)) if self.lenient_fd_negotiation => {
+                    return Ok(Command::Error(format!("Unexpected reply: {e}")));
+                }
+                Err(e) => return Err(e),
+            };
+            match cmd {
+                Command::Data(_) if self.lenient_fd_negotiation => {
+                    warn!("Received DATA during UNIX FD negotiation, replying with empty DATA");
+                    self.common.write_command(Command::Data(None)).await?;
+                }
+                Command::Ok(_) | Command::AgreeUnixFD | Command::Error(_) => return Ok(cmd),
+                // Callers log the error as FD passing being rejected.
+                cmd if self.lenient_fd_negotiation => {
+                    return Ok(Command::Error(format!("Unexpected reply: {cmd}")))
+                }
+                cmd => return Ok(cmd),
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -179,24 +239,42 @@ impl Handshake for Client {
         #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
         self.send_zero_byte().await?;
 
-        self.authenticate().await?;
-        let expected_n_responses = self.send_secondary_commands().await?;
+        self.authenticate()
+            .await
+            .map_err(|e| step_error("authentication", e))?;
+        let expected_n_responses = self
+            .send_secondary_commands()
+            .await
+            .map_err(|e| step_error("negotiation", e))?;
 
         if expected_n_responses > 0 {
             self.receive_secondary_responses(expected_n_responses)
-                .await?;
+                .await
+                .map_err(|e| step_error("negotiation", e))?;
         }
 
-        trace!("Handshake done");
+        let transcript = self.common.take_transcript();
         #[cfg(unix)]
-        let (socket, mut recv_buffer, received_fds, cap_unix_fd, _) = self.common.into_components();
+        let unix_fd_requested = self.common.unix_fd_requested();
+        #[cfg(unix)]
+        let (socket, mut recv_buffer, received_fds, cap_unix_fd, mechanism) =
+            self.common.into_components();
         #[cfg(not(unix))]
-        let (socket, mut recv_buffer, _, _) = self.common.into_components();
+        let (socket, mut recv_buffer, cap_unix_fd, mechanism) = self.common.into_components();
+        // Field names match the ones used by the server when rejecting a client.
+        debug!(
+            dbus_auth_mech = %mechanism,
+            dbus_cap_unix_fd = cap_unix_fd,
+            dbus_server_guid = %self.server_guid.as_ref().unwrap(),
+            "Handshake done"
+        );
         let (mut read, write) = socket.take();
 
         // If we're a bus connection, we need to read the unique name from `Hello` response.
         let unique_name = if self.bus {
-            let unique_name = receive_hello_response(&mut read, &mut recv_buffer).await?;
+            let unique_name = receive_hello_response(&mut read, &mut recv_buffer)
+                .await
+                .map_err(|e| step_error("Hello", e))?;
 
             Some(unique_name)
         } else {
@@ -209,10 +287,13 @@ impl Handshake for Client {
             server_guid: self.server_guid.unwrap(),
             #[cfg(unix)]
             cap_unix_fd,
+            #[cfg(unix)]
+            unix_fd_requested,
             already_received_bytes: recv_buffer,
             #[cfg(unix)]
             already_received_fds: received_fds,
             unique_name,
+            transcript,
         })
     }
 }
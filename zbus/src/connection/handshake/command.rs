@@ -80,17 +80,20 @@ impl FromStr for Command {
             Some("ERROR") => Command::Error(s.into()),
             Some("NEGOTIATE_UNIX_FD") => Command::NegotiateUnixFD,
             Some("REJECTED") => {
-                let mechs = words.map(|s| s.to_owned()).collect();
-                Command::Rejected(mechs)
+                let mechs = words.collect::<Vec<_>>().join(" ");
+                Command::Rejected(mechs.into())
             }
             Some("OK") => {
                 let guid = words
                     .next()
                     .ok_or_else(|| Error::Handshake("Missing OK server GUID!".into()))?;
-                Command::Ok(Guid::from_str(guid)?.into())
+                let guid = Guid::from_str(guid)
+                    .map_err(|_| Error::Handshake(format!("Invalid server GUID '{guid}'")))?;
+                Command::Ok(guid.into())
             }
             Some("AGREE_UNIX_FD") => Command::AgreeUnixFD,
-            _ => return Err(Error::Handshake(format!("Unknown command: {s}"))),
+            // The line isn't included since it may contain credentials (e.g. a lowercase `auth`).
+            _ => return Err(Error::Handshake("Unknown command".into())),
         };
         Ok(cmd)
     }
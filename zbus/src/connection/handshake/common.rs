@@ -1,18 +1,37 @@
-use tracing::{instrument, trace};
+use tracing::{instrument, trace, warn};
 
-use super::{AuthMechanism, BoxedSplit, Command};
+use super::{
+    transcript::{Transcript, TranscriptDirection},
+    AuthMechanism, BoxedSplit, Command, TranscriptEntry, TranscriptMode,
+};
 use crate::{Error, Result};
 
+/// The maximum number of bytes we are willing to receive over the course of a handshake.
+///
+/// A legitimate handshake is only a few short lines so this is very generous, while still
+/// preventing a peer from making us buffer unbounded amounts of data, e.g. by slowly sending a never
+/// ending command line.
+const MAX_HANDSHAKE_DATA: usize = 64 * 1024;
+
+/// How much room we make in the receive buffer for each read from the socket.
+const READ_CHUNK_SIZE: usize = 1024;
+
 // Common code for the client and server side of the handshake.
 #[derive(Debug)]
 pub(super) struct Common {
     socket: BoxedSplit,
     recv_buffer: Vec<u8>,
+    total_received: usize,
     #[cfg(unix)]
     received_fds: Vec<std::os::fd::OwnedFd>,
     cap_unix_fd: bool,
+    #[cfg(unix)]
+    unix_fd_requested: bool,
     mechanism: AuthMechanism,
     first_command: bool,
+    lenient_leading_nul: bool,
+    lenient_line_endings: bool,
+    transcript: Option<Transcript>,
 }
 
 impl Common {
@@ -21,11 +40,17 @@ impl Common {
         Self {
             socket,
             recv_buffer: Vec::new(),
+            total_received: 0,
             #[cfg(unix)]
             received_fds: Vec::new(),
             cap_unix_fd: false,
+            #[cfg(unix)]
+            unix_fd_requested: false,
             mechanism,
             first_command: true,
+            lenient_leading_nul: false,
+            lenient_line_endings: false,
+            transcript: None,
         }
     }
 
@@ -38,14 +63,53 @@ impl Common {
         &mut self.socket
     }
 
+    #[cfg(feature = "p2p")]
+    pub fn cap_unix_fd(&self) -> bool {
+        self.cap_unix_fd
+    }
+
     pub fn set_cap_unix_fd(&mut self, cap_unix_fd: bool) {
         self.cap_unix_fd = cap_unix_fd;
     }
 
+    /// Whether `NEGOTIATE_UNIX_FD` was sent (client) or received (server), agreed or not.
+    #[cfg(unix)]
+    pub fn unix_fd_requested(&self) -> bool {
+        self.unix_fd_requested
+    }
+
+    #[cfg(unix)]
+    pub fn set_unix_fd_requested(&mut self) {
+        self.unix_fd_requested = true;
+    }
+
     pub fn mechanism(&self) -> AuthMechanism {
         self.mechanism
     }
 
+    /// Skip any extra NUL bytes the client sends before its first command.
+    #[cfg(feature = "p2p")]
+    pub fn set_lenient_leading_nul(&mut self, lenient: bool) {
+        self.lenient_leading_nul = lenient;
+    }
+
+    /// Accept a lone `\n` as line terminator.
+    pub fn set_lenient_line_endings(&mut self, lenient: bool) {
+        self.lenient_line_endings = lenient;
+    }
+
+    /// Start recording all the exchanged commands.
+    pub fn record_transcript(&mut self, mode: TranscriptMode) {
+        self.transcript = Some(Transcript::new(mode));
+    }
+
+    pub fn take_transcript(&mut self) -> Vec<TranscriptEntry> {
+        self.transcript
+            .take()
+            .map(Transcript::into_entries)
+            .unwrap_or_default()
+    }
+
     pub fn into_components(self) -> IntoComponentsReturn {
         (
             self.socket,
@@ -68,6 +132,11 @@ impl Common {
         commands: &[Command],
         extra_bytes: Option<&[u8]>,
     ) -> Result<()> {
+        if let Some(transcript) = &mut self.transcript {
+            for command in commands {
+                transcript.record(TranscriptDirection::Sent, command, &command.to_string());
+            }
+        }
         let mut send_buffer =
             commands
                 .iter()
@@ -97,6 +166,10 @@ impl Common {
                     &[],
                 )
                 .await?;
+            if written == 0 {
+                // Retrying would just spin forever without making any progress.
+                return Err(Error::Handshake("Write made no progress".into()));
+            }
             send_buffer.drain(..written);
         }
         trace!("Wrote all commands");
@@ -105,50 +178,114 @@ impl Common {
 
     #[instrument(skip(self))]
     pub async fn read_command(&mut self) -> Result<Command> {
-        self.read_commands(1)
-            .await
-            .map(|cmds| cmds.into_iter().next().unwrap())
+        self.try_read_command().await?
     }
 
+    /// Read the next command.
+    ///
+    /// The outer result is the outcome of reading the line, the inner one of parsing it as a
+    /// command.
     #[instrument(skip(self))]
-    pub async fn read_commands(&mut self, n_commands: usize) -> Result<Vec<Command>> {
-        let mut commands = Vec::with_capacity(n_commands);
-        let mut n_received_commands = 0;
-        'outer: loop {
-            while let Some(lf_index) = self.recv_buffer.iter().position(|b| *b == b'\n') {
-                if self.recv_buffer[lf_index - 1] != b'\r' {
-                    return Err(Error::Handshake("Invalid line ending in handshake".into()));
+    pub async fn try_read_command(&mut self) -> Result<Result<Command>> {
+        let (start, end) = self.read_line().await?;
+        // The line is parsed in place, before being removed from the buffer.
+        let line_bytes = &self.recv_buffer[start..end];
+        let res = match std::str::from_utf8(line_bytes) {
+            Ok(line) => {
+                trace!("Reading {line}");
+                match line.parse() {
+                    Ok(command) => {
+                        if let Some(transcript) = &mut self.transcript {
+                            transcript.record(
+                                TranscriptDirection::Received,
+                                &command,
+                                line.trim_end(),
+                            );
+                        }
+
+                        Ok(Ok(command))
+                    }
+                    Err(Error::Handshake(e)) => Ok(Err(Error::Handshake(format!(
+                        "{e} (got {})",
+                        dump_bytes(line_bytes)
+                    )))),
+                    Err(e) => Ok(Err(e)),
+                }
+            }
+            Err(e) => Err(Error::Handshake(format!(
+                "{e} (got {})",
+                dump_bytes(line_bytes)
+            ))),
+        };
+        self.recv_buffer.drain(..end);
+
+        res
+    }
+
+    // Wait for the next complete line and return its bounds in the receive buffer, without the
+    // leading NUL byte(s) of the first line.
+    async fn read_line(&mut self) -> Result<(usize, usize)> {
+        loop {
+            if self.first_command && self.recv_buffer.first().is_some_and(|b| *b != b'\0') {
+                // The first command is sent by the client so we can assume it's the server.
+                return Err(Error::Handshake(format!(
+                    "First client byte is not NUL ({})",
+                    sniff_protocol(&self.recv_buffer),
+                )));
+            }
+
+            if let Some(lf_index) = self.recv_buffer.iter().position(|b| *b == b'\n') {
+                // Only a `\r\n` pair terminates a command. Note that the `\r` and the `\n` may have
+                // arrived in separate reads, and that the `\n` could be the very first byte left in
+                // the buffer.
+                if (lf_index == 0 || self.recv_buffer[lf_index - 1] != b'\r')
+                    && !self.lenient_line_endings
+                {
+                    return Err(Error::Handshake(format!(
+                        "Invalid line ending in handshake (got {})",
+                        dump_bytes(&self.recv_buffer[..=lf_index]),
+                    )));
                 }
 
                 #[allow(unused_mut)]
                 let mut start_index = 0;
                 if self.first_command {
-                    // The first command is sent by the client so we can assume it's the server.
+                    // Skip the NUL byte, checked above.
                     self.first_command = false;
-                    if self.recv_buffer[0] != b'\0' {
-                        return Err(Error::Handshake(
-                            "First client byte is not NUL!".to_string(),
-                        ));
-                    }
-
                     start_index = 1;
+                    if self.lenient_leading_nul {
+                        let extra = self.recv_buffer[1..lf_index]
+                            .iter()
+                            .take_while(|b| **b == b'\0')
+                            .count();
+                        if extra > 0 {
+                            warn!("Skipping {extra} extra leading NUL byte(s) sent by the client");
+                            start_index += extra;
+                        }
+                    }
                 };
 
-                let line_bytes = self.recv_buffer.drain(..=lf_index);
-                let line = std::str::from_utf8(&line_bytes.as_slice()[start_index..])
-                    .map_err(|e| Error::Handshake(e.to_string()))?;
+                return Ok((start_index, lf_index + 1));
+            }
 
-                trace!("Reading {line}");
-                commands.push(line.parse()?);
-                n_received_commands += 1;
+            // Read directly into the spare room at the end of the receive buffer, so we neither
+            // allocate a temporary buffer for each read nor copy the data around afterwards. Once
+            // the handshake is done, the buffer is passed on to the connection as is.
+            let filled = self.recv_buffer.len();
+            self.recv_buffer.resize(filled + READ_CHUNK_SIZE, 0);
+            let res = match self
+                .socket
+                .read_mut()
+                .recvmsg(&mut self.recv_buffer[filled..])
+                .await
+            {
+                Ok(res) => res,
+                Err(e) => {
+                    self.recv_buffer.truncate(filled);
 
-                if n_received_commands == n_commands {
-                    break 'outer;
+                    return Err(e.into());
                 }
-            }
-
-            let mut buf = vec![0; 1024];
-            let res = self.socket.read_mut().recvmsg(&mut buf).await?;
+            };
             let read = {
                 #[cfg(unix)]
                 {
@@ -164,13 +301,20 @@ impl Common {
                     res
                 }
             };
+            self.recv_buffer.truncate(filled + read);
             if read == 0 {
-                return Err(Error::Handshake("Unexpected EOF during handshake".into()));
+                let mut msg = "Unexpected EOF during handshake".to_string();
+                if !self.recv_buffer.is_empty() {
+                    msg.push_str(&format!(" (unparsed {})", dump_bytes(&self.recv_buffer)));
+                }
+
+                return Err(Error::Handshake(msg));
+            }
+            self.total_received += read;
+            if self.total_received > MAX_HANDSHAKE_DATA {
+                return Err(Error::Handshake("Handshake data limit exceeded".into()));
             }
-            self.recv_buffer.extend(&buf[..read]);
         }
-
-        Ok(commands)
     }
 }
 
@@ -184,3 +328,95 @@ type IntoComponentsReturn = (
 );
 #[cfg(not(unix))]
 type IntoComponentsReturn = (BoxedSplit, Vec<u8>, bool, AuthMechanism);
+
+/// Format handshake data for error messages, as hex and ASCII: `52 45 4a ("REJ")`.
+///
+/// Only the first few bytes are shown and anything following `AUTH <mechanism>` or `DATA` is
+/// redacted, since it may contain credentials.
+fn dump_bytes(data: &[u8]) -> String {
+    const MAX_LEN: usize = 32;
+
+    // Be liberal in what is taken for a command carrying credentials, since the data is dumped
+    // precisely because it's malformed.
+    let start = data
+        .iter()
+        .take_while(|b| **b == b'\0' || b.is_ascii_whitespace())
+        .count();
+    let word = |word: &[u8]| {
+        data[start..]
+            .get(..word.len())
+            .is_some_and(|w| w.eq_ignore_ascii_case(word))
+    };
+    let secret_start = if word(b"AUTH") {
+        // Only show the mechanism if it's one we know, so it can't be mixed up with the identity.
+        let mech_start = start + 5;
+        let mech_len = data
+            .get(mech_start..)
+            .and_then(|rest| rest.iter().position(|b| matches!(b, b' ' | b'\r' | b'\n')))
+            .filter(|_| data[start + 4] == b' ')
+            .filter(|len| {
+                std::str::from_utf8(&data[mech_start..mech_start + len])
+                    .is_ok_and(|mech| mech.parse::<AuthMechanism>().is_ok())
+            });
+        Some(mech_len.map_or(start + 4, |len| mech_start + len))
+    } else if word(b"DATA") {
+        Some(start + 4)
+    } else {
+        None
+    }
+    // Nothing to redact if only the line ending follows.
+    .filter(|secret_start| !data[*secret_start..].iter().all(u8::is_ascii_whitespace));
+    let visible = &data[..secret_start.unwrap_or(data.len()).min(MAX_LEN)];
+
+    let hex = visible
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let ascii: String = visible
+        .iter()
+        .map(|b| match b {
+            b' '..=b'~' => *b as char,
+            _ => '.',
+        })
+        .collect();
+    let suffix = if secret_start.is_some() {
+        " [REDACTED]"
+    } else if visible.len() < data.len() {
+        " ..."
+    } else {
+        ""
+    };
+
+    format!("{hex} (\"{ascii}\"){suffix}")
+}
+
+/// Make a guess at the protocol spoken by a peer that doesn't speak D-Bus, from its first bytes.
+fn sniff_protocol(data: &[u8]) -> String {
+    const HTTP_METHODS: &[&[u8]] = &[
+        b"GET ",
+        b"HEAD ",
+        b"POST ",
+        b"PUT ",
+        b"DELETE ",
+        b"OPTIONS ",
+        b"CONNECT ",
+        b"PATCH ",
+    ];
+
+    if HTTP_METHODS.iter().any(|m| data.starts_with(m)) {
+        let request_line = data
+            .split(|b| *b == b'\r' || *b == b'\n')
+            .next()
+            .unwrap_or(data);
+        let request_line = String::from_utf8_lossy(&request_line[..request_line.len().min(32)]);
+
+        format!("looks like HTTP: {request_line}")
+    } else if data.starts_with(b"SSH-") {
+        "looks like SSH".into()
+    } else if data.starts_with(&[0x16, 0x03]) {
+        "looks like a TLS ClientHello".into()
+    } else {
+        "unknown protocol".into()
+    }
+}
@@ -0,0 +1,21 @@
+/// What a server does when the kernel can't tell it the credentials of the client.
+///
+/// This only matters for the `EXTERNAL` mechanism, which relies on the credentials the server
+/// gets from the socket (e.g. through `SO_PEERCRED`) to verify the identity claimed by the client.
+/// Some sockets can't provide them, either because the platform doesn't support it for the socket
+/// type or because the socket isn't a Unix socket in the first place.
+///
+/// See [`crate::connection::Builder::credential_policy`] for how to set it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CredentialPolicy {
+    /// Fail the handshake.
+    #[default]
+    Required,
+    /// Trust the identity claimed by the client.
+    ///
+    /// Only use this if the transport is trusted by other means since any client can claim to be
+    /// any user.
+    TrustClaimed,
+    /// Reject the authentication attempt, letting the client try another mechanism.
+    Reject,
+}
@@ -0,0 +1,88 @@
+#[cfg(unix)]
+use nix::unistd::Uid;
+
+use super::{sasl_auth_id, AuthMechanism, Command};
+use crate::Result;
+
+/// The authorization identity to send with the `EXTERNAL` authentication mechanism.
+///
+/// Message buses expect the UID of the connecting process on Unix (and its SID on Windows). By
+/// default, zbus uses the effective UID, which is also what the bus sees through the socket
+/// credentials. When the real and effective UIDs differ (e.g. in a setuid program), you may need to
+/// pick one explicitly.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExternalIdentity {
+    /// The effective UID of the current process on Unix, or its SID on Windows.
+    #[default]
+    EffectiveUid,
+    /// The real UID of the current process.
+    #[cfg(unix)]
+    RealUid,
+    /// The given UID.
+    #[cfg(unix)]
+    Explicit(u32),
+    /// An arbitrary identity.
+    ///
+    /// As far as SASL is concerned, the identity is an opaque byte string (hex-encoded on the
+    /// wire), so this can be used for peers that use an identifier other than the UID.
+    Raw(Vec<u8>),
+}
+
+impl ExternalIdentity {
+    /// The identity, hex-encoded as sent in the `AUTH EXTERNAL` command.
+    ///
+    /// For example, the identity for UID 1000 is `31303030`.
+    pub fn to_hex(&self) -> Result<String> {
+        self.to_bytes().map(hex::encode)
+    }
+
+    /// The complete `AUTH EXTERNAL` line a client sends for this identity, as the first thing on
+    /// the wire.
+    ///
+    /// This includes the NUL byte that must precede the first command of the client and the
+    /// trailing `\r\n`. For example, the line for UID 1000 is `\0AUTH EXTERNAL 31303030\r\n`.
+    ///
+    /// On FreeBSD and DragonFly, zbus sends the NUL byte on its own (along with the credentials of
+    /// the process) but the resulting byte stream is the same.
+    pub fn auth_line(&self) -> Result<Vec<u8>> {
+        let mut line = vec![b'\0'];
+        line.extend(Vec::<u8>::from(&self.auth_command()?));
+        line.extend_from_slice(b"\r\n");
+
+        Ok(line)
+    }
+
+    /// The `AUTH EXTERNAL` command for this identity.
+    pub(super) fn auth_command(&self) -> Result<Command> {
+        Ok(Command::Auth(
+            Some(AuthMechanism::External),
+            Some(self.to_bytes()?),
+        ))
+    }
+
+    /// The identity as sent (before hex-encoding) in the `AUTH EXTERNAL` command.
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let id = match self {
+            ExternalIdentity::EffectiveUid => sasl_auth_id()?,
+            #[cfg(unix)]
+            ExternalIdentity::RealUid => Uid::current().to_string(),
+            #[cfg(unix)]
+            ExternalIdentity::Explicit(uid) => uid.to_string(),
+            ExternalIdentity::Raw(id) => return Ok(id.clone()),
+        };
+
+        Ok(id.into_bytes())
+    }
+}
+
+impl From<Vec<u8>> for ExternalIdentity {
+    fn from(id: Vec<u8>) -> Self {
+        ExternalIdentity::Raw(id)
+    }
+}
+
+impl From<&[u8]> for ExternalIdentity {
+    fn from(id: &[u8]) -> Self {
+        ExternalIdentity::Raw(id.to_vec())
+    }
+}
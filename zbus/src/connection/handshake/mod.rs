@@ -3,7 +3,12 @@ mod client;
 mod command;
 mod common;
 #[cfg(feature = "p2p")]
+mod credential_policy;
+mod external_identity;
+mod options;
+#[cfg(feature = "p2p")]
 mod server;
+mod transcript;
 
 use async_trait::async_trait;
 #[cfg(unix)]
@@ -22,7 +27,12 @@ use client::Client;
 use command::Command;
 use common::Common;
 #[cfg(feature = "p2p")]
+pub use credential_policy::CredentialPolicy;
+pub use external_identity::ExternalIdentity;
+pub use options::HandshakeOptions;
+#[cfg(feature = "p2p")]
 use server::Server;
+pub use transcript::{TranscriptDirection, TranscriptEntry, TranscriptMode};
 
 /// The result of a finalized handshake
 ///
@@ -38,12 +48,16 @@ pub struct Authenticated {
     /// Whether file descriptor passing has been accepted by both sides
     #[cfg(unix)]
     pub(crate) cap_unix_fd: bool,
+    /// Whether file descriptor passing was requested, regardless of whether it was accepted
+    #[cfg(unix)]
+    pub(crate) unix_fd_requested: bool,
 
     pub(crate) socket_read: Option<Box<dyn ReadHalf>>,
     pub(crate) already_received_bytes: Vec<u8>,
     #[cfg(unix)]
     pub(crate) already_received_fds: Vec<std::os::fd::OwnedFd>,
     pub(crate) unique_name: Option<OwnedUniqueName>,
+    pub(crate) transcript: Vec<TranscriptEntry>,
 }
 
 impl Authenticated {
@@ -52,11 +66,18 @@ impl Authenticated {
         socket: BoxedSplit,
         server_guid: Option<OwnedGuid>,
         mechanism: Option<AuthMechanism>,
+        external_id: Option<ExternalIdentity>,
         bus: bool,
+        options: HandshakeOptions,
     ) -> Result<Self> {
-        Client::new(socket, mechanism, server_guid, bus)
-            .perform()
-            .await
+        let mut client = Client::new(socket, mechanism, external_id, server_guid, bus);
+        client.set_lenient_fd_negotiation(options.lenient_fd_negotiation);
+        client.set_lenient_line_endings(options.lenient_line_endings);
+        if let Some(mode) = options.transcript {
+            client.record_transcript(mode);
+        }
+
+        client.perform().await
     }
 
     /// Create a server-side `Authenticated` for the given `socket`.
@@ -70,8 +91,9 @@ impl Authenticated {
         #[cfg(windows)] client_sid: Option<String>,
         auth_mechanism: Option<AuthMechanism>,
         unique_name: Option<OwnedUniqueName>,
+        options: HandshakeOptions,
     ) -> Result<Self> {
-        Server::new(
+        let mut server = Server::new(
             socket,
             guid,
             #[cfg(unix)]
@@ -80,9 +102,24 @@ impl Authenticated {
             client_sid,
             auth_mechanism,
             unique_name,
-        )?
-        .perform()
-        .await
+        )?;
+        server.set_credential_policy(options.credential_policy);
+        server.set_skip_identity_check(options.skip_identity_check);
+        server.set_lenient_leading_nul(options.lenient_leading_nul);
+        server.set_lenient_line_endings(options.lenient_line_endings);
+        if let Some(mode) = options.transcript {
+            server.record_transcript(mode);
+        }
+
+        server.perform().await
+    }
+
+    /// Take the bytes received past the end of the handshake.
+    ///
+    /// These belong to the first message(s) from the peer and must be handed to whatever reads
+    /// messages from the socket. Subsequent calls return an empty buffer.
+    pub fn take_leftover(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.already_received_bytes)
     }
 }
 
@@ -95,6 +132,14 @@ pub trait Handshake {
     async fn perform(mut self) -> Result<Authenticated>;
 }
 
+/// Add the handshake step to handshake errors.
+fn step_error(step: &str, e: Error) -> Error {
+    match e {
+        Error::Handshake(e) => Error::Handshake(format!("{e} (during {step})")),
+        e => e,
+    }
+}
+
 fn sasl_auth_id() -> Result<String> {
     let id = {
         #[cfg(unix)]
@@ -130,7 +175,10 @@ mod tests {
 
     use super::*;
 
-    use crate::{connection::Socket, Guid};
+    use crate::{
+        connection::{socket::memory::MemSocket, Socket},
+        Guid,
+    };
 
     fn create_async_socket_pair() -> (impl AsyncWrite + Socket, impl AsyncWrite + Socket) {
         // Tokio needs us to call the sync function from async context. :shrug:
@@ -157,7 +205,7 @@ mod tests {
         let (p0, p1) = create_async_socket_pair();
 
         let guid = OwnedGuid::from(Guid::generate());
-        let client = Client::new(p0.into(), None, Some(guid.clone()), false);
+        let client = Client::new(p0.into(), None, None, Some(guid.clone()), false);
         let server =
             Server::new(p1.into(), guid, Some(Uid::effective().into()), None, None).unwrap();
 
@@ -169,6 +217,7 @@ mod tests {
 
         assert_eq!(client.server_guid, server.server_guid);
         assert_eq!(client.cap_unix_fd, server.cap_unix_fd);
+        assert_eq!(client.unix_fd_requested, server.unix_fd_requested);
     }
 
     #[test]
@@ -276,4 +325,930 @@ mod tests {
             .unwrap();
         crate::utils::block_on(server.perform()).unwrap();
     }
+
+    #[test]
+    #[timeout(15000)]
+    fn byte_split_terminator() {
+        let socket = MemSocket::new()
+            .push_read(format!(
+                "\0AUTH EXTERNAL {}\r",
+                hex::encode(sasl_auth_id().unwrap())
+            ))
+            .push_read("\nBEGIN\r")
+            .push_read("\n");
+        let written = socket.written();
+        let guid = OwnedGuid::from(Guid::generate());
+        let server = Server::new(
+            socket.into(),
+            guid.clone(),
+            Some(Uid::effective().into()),
+            None,
+            None,
+        )
+        .unwrap();
+
+        crate::utils::block_on(server.perform()).unwrap();
+        assert_eq!(
+            *written.lock().unwrap(),
+            format!("OK {guid}\r\n").as_bytes()
+        );
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn duplicate_fd_negotiation() {
+        let socket = MemSocket::new().push_read(
+            "\0AUTH ANONYMOUS 7a627573\r\nNEGOTIATE_UNIX_FD\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n",
+        );
+        let written = socket.written();
+        let guid = OwnedGuid::from(Guid::generate());
+        let server = Server::new(
+            socket.into(),
+            guid.clone(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+
+        let server = crate::utils::block_on(server.perform()).unwrap();
+        assert!(server.cap_unix_fd);
+        assert_eq!(
+            *written.lock().unwrap(),
+            format!("OK {guid}\r\nAGREE_UNIX_FD\r\nERROR UNIX FD passing already negotiated\r\n")
+                .as_bytes()
+        );
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn fd_negotiation_requested() {
+        // The client doesn't ask, so there's nothing to decline.
+        let socket = MemSocket::new().push_read("\0AUTH ANONYMOUS 7a627573\r\nBEGIN\r\n");
+        let server = Server::new(
+            socket.into(),
+            Guid::generate().into(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+        let server = crate::utils::block_on(server.perform()).unwrap();
+        assert!(!server.unix_fd_requested);
+        assert!(!server.cap_unix_fd);
+
+        // The client asks but the socket can't pass FDs, so the server declines.
+        let socket = MemSocket::new()
+            .push_read("\0AUTH ANONYMOUS 7a627573\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n")
+            .no_fd_passing();
+        let server = Server::new(
+            socket.into(),
+            Guid::generate().into(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+        let server = crate::utils::block_on(server.perform()).unwrap();
+        assert!(server.unix_fd_requested);
+        assert!(!server.cap_unix_fd);
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn embedded_carriage_return() {
+        // The lone `\r` must not be mistaken for a line terminator. If it were, `cd` would be
+        // parsed as a separate (unknown) command.
+        let socket = MemSocket::new().push_read("\0AUTH ANONYMOUS ab\rcd\r\nBEGIN\r\n");
+        let server = Server::new(
+            socket.into(),
+            Guid::generate().into(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+
+        crate::utils::block_on(server.perform()).unwrap();
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn leading_nul_bytes() {
+        for (reads, lenient, ok) in [
+            (&["\0AUTH ANONYMOUS 7a627573\r\nBEGIN\r\n"][..], false, true),
+            (&["\0\0AUTH ANONYMOUS 7a627573\r\nBEGIN\r\n"], false, false),
+            (&["\0AUTH ANONYMOUS 7a627573\r\nBEGIN\r\n"], true, true),
+            (&["\0\0AUTH ANONYMOUS 7a627573\r\nBEGIN\r\n"], true, true),
+            (
+                &["\0", "\0\0AUTH ANONYMOUS 7a627573\r\nBEGIN\r\n"],
+                true,
+                true,
+            ),
+        ] {
+            let socket = reads
+                .iter()
+                .fold(MemSocket::new(), |socket, data| socket.push_read(*data));
+            let mut server = Server::new(
+                socket.into(),
+                Guid::generate().into(),
+                Some(Uid::effective().into()),
+                Some(AuthMechanism::Anonymous),
+                None,
+            )
+            .unwrap();
+            server.set_lenient_leading_nul(lenient);
+
+            let res = crate::utils::block_on(server.perform());
+            assert_eq!(res.is_ok(), ok, "{reads:?} (lenient: {lenient}): {res:?}");
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn lone_line_feed() {
+        for data in [
+            "\0AUTH ANONYMOUS abcd\n",
+            "\0AUTH ANONYMOUS abcd\r\n\nBEGIN\r\n",
+        ] {
+            let socket = MemSocket::new().push_read(data);
+            let server = Server::new(
+                socket.into(),
+                Guid::generate().into(),
+                Some(Uid::effective().into()),
+                Some(AuthMechanism::Anonymous),
+                None,
+            )
+            .unwrap();
+
+            let err = crate::utils::block_on(server.perform()).unwrap_err();
+            assert!(
+                matches!(err, Error::Handshake(ref e) if e.starts_with("Invalid line ending in handshake")),
+                "unexpected error: {err}",
+            );
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn lenient_line_endings() {
+        let socket = MemSocket::new().push_read("\0AUTH ANONYMOUS 7a627573\nBEGIN\n");
+        let mut server = Server::new(
+            socket.into(),
+            Guid::generate().into(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+        server.set_lenient_line_endings(true);
+        crate::utils::block_on(server.perform()).unwrap();
+
+        let guid = OwnedGuid::from(Guid::generate());
+        let socket = MemSocket::new().push_read(format!("OK {guid}\nAGREE_UNIX_FD\n"));
+        let mut client = Client::new(
+            socket.into(),
+            Some(AuthMechanism::Anonymous),
+            None,
+            None,
+            false,
+        );
+        client.set_lenient_line_endings(true);
+        let client = crate::utils::block_on(client.perform()).unwrap();
+        assert_eq!(client.server_guid, guid);
+        assert!(client.cap_unix_fd);
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn external_raw_identity() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let socket = MemSocket::new()
+            .push_read(format!("OK {guid}\r\n"))
+            .push_read("AGREE_UNIX_FD\r\n");
+        let written = socket.written();
+        let client = Client::new(
+            socket.into(),
+            Some(AuthMechanism::External),
+            Some(b"not-a-uid\0".to_vec().into()),
+            None,
+            false,
+        );
+
+        let client = crate::utils::block_on(client.perform()).unwrap();
+        assert_eq!(client.server_guid, guid);
+        assert!(written
+            .lock()
+            .unwrap()
+            .starts_with(b"\0AUTH EXTERNAL 6e6f742d612d75696400\r\n"));
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn no_fd_negotiation_without_fd_passing() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let socket = MemSocket::new()
+            .push_read(format!("OK {guid}\r\n"))
+            .no_fd_passing();
+        let written = socket.written();
+        let client = Client::new(
+            socket.into(),
+            Some(AuthMechanism::External),
+            Some(b"1000".to_vec().into()),
+            None,
+            false,
+        );
+
+        let client = crate::utils::block_on(client.perform()).unwrap();
+        assert!(!client.cap_unix_fd);
+        assert!(!client.unix_fd_requested);
+        assert_eq!(
+            *written.lock().unwrap(),
+            b"\0AUTH EXTERNAL 31303030\r\nBEGIN\r\n"
+        );
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn transcript() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let socket = MemSocket::new()
+            .push_read(format!("OK {guid}\r\n"))
+            .push_read("AGREE_UNIX_FD\r\n");
+        let mut client = Client::new(
+            socket.into(),
+            Some(AuthMechanism::External),
+            Some(b"secret".to_vec().into()),
+            None,
+            false,
+        );
+        client.record_transcript(TranscriptMode::Redacted);
+
+        let client = crate::utils::block_on(client.perform()).unwrap();
+        let transcript: Vec<_> = client
+            .transcript
+            .iter()
+            .map(|e| (e.direction(), e.line()))
+            .collect();
+        let ok = format!("OK {guid}");
+        assert_eq!(
+            transcript,
+            [
+                (TranscriptDirection::Sent, "AUTH EXTERNAL [REDACTED]"),
+                (TranscriptDirection::Received, ok.as_str()),
+                (TranscriptDirection::Sent, "NEGOTIATE_UNIX_FD"),
+                (TranscriptDirection::Sent, "BEGIN"),
+                (TranscriptDirection::Received, "AGREE_UNIX_FD"),
+            ]
+        );
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn lenient_fd_negotiation() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let new_client = |lenient| {
+            let socket = MemSocket::new()
+                .push_read(format!("OK {guid}\r\n"))
+                .push_read("DATA\r\n")
+                .push_read("AGREE_UNIX_FD\r\n");
+            let written = socket.written();
+            let mut client = Client::new(
+                socket.into(),
+                Some(AuthMechanism::Anonymous),
+                None,
+                None,
+                false,
+            );
+            client.set_lenient_fd_negotiation(lenient);
+
+            (client, written)
+        };
+
+        let (client, _) = new_client(false);
+        crate::utils::block_on(client.perform()).unwrap_err();
+
+        let (client, written) = new_client(true);
+        let client = crate::utils::block_on(client.perform()).unwrap();
+        assert!(client.cap_unix_fd);
+        assert!(written
+            .lock()
+            .unwrap()
+            .ends_with(b"NEGOTIATE_UNIX_FD\r\nDATA\r\nBEGIN\r\n"));
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn lenient_fd_negotiation_unexpected_reply() {
+        let guid = OwnedGuid::from(Guid::generate());
+        for reply in ["\r\n", "AUTH\r\n", "DATA zz\r\n"] {
+            let new_client = |lenient| {
+                let socket = MemSocket::new()
+                    .push_read(format!("OK {guid}\r\n"))
+                    .push_read(reply);
+                let mut client = Client::new(
+                    socket.into(),
+                    Some(AuthMechanism::Anonymous),
+                    None,
+                    None,
+                    false,
+                );
+                client.set_lenient_fd_negotiation(lenient);
+
+                client
+            };
+
+            crate::utils::block_on(new_client(false).perform()).unwrap_err();
+
+            let client = crate::utils::block_on(new_client(true).perform()).unwrap();
+            assert!(!client.cap_unix_fd, "{reply:?}");
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn server_guid_mismatch() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let actual = OwnedGuid::from(Guid::generate());
+        let socket = MemSocket::new().push_read(format!("OK {actual}\r\n"));
+        let client = Client::new(
+            socket.into(),
+            Some(AuthMechanism::Anonymous),
+            None,
+            Some(guid.clone()),
+            false,
+        );
+
+        let err = crate::utils::block_on(client.perform()).unwrap_err();
+        assert_eq!(err, Error::GuidMismatch(guid, actual));
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn auth_rejected() {
+        let socket = MemSocket::new().push_read("REJECTED EXTERNAL DBUS_COOKIE_SHA1\r\n");
+        let client = Client::new(
+            socket.into(),
+            Some(AuthMechanism::Anonymous),
+            None,
+            None,
+            false,
+        );
+
+        let err = crate::utils::block_on(client.perform()).unwrap_err();
+        match err {
+            Error::AuthRejected(mechanism, accepted) => {
+                assert_eq!(mechanism, AuthMechanism::Anonymous);
+                assert_eq!(accepted, ["EXTERNAL", "DBUS_COOKIE_SHA1"]);
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn not_dbus() {
+        for (data, hint) in [
+            (
+                &b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n"[..],
+                "looks like HTTP: GET /index.html HTTP/1.1",
+            ),
+            (b"SSH-2.0-OpenSSH_9.6\r\n", "looks like SSH"),
+            (
+                &[0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00],
+                "looks like a TLS ClientHello",
+            ),
+            (b"AUTH ANONYMOUS\r\n", "unknown protocol"),
+        ] {
+            let socket = MemSocket::new().push_read(data);
+            let server = Server::new(
+                socket.into(),
+                Guid::generate().into(),
+                Some(Uid::effective().into()),
+                Some(AuthMechanism::Anonymous),
+                None,
+            )
+            .unwrap();
+
+            let err = crate::utils::block_on(server.perform()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "D-Bus handshake failed: First client byte is not NUL ({hint}) \
+                     (during authentication)"
+                ),
+            );
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn external_uid_values() {
+        for (id, accepted) in [
+            (&b"0"[..], true),
+            (b"00", true),
+            (b"+0", false),
+            (b"0x0", false),
+            (b"4294967295", false),
+            (b"4294967296", false),
+            // Not a UID at all, e.g. from `ExternalIdentity::Raw`.
+            (b"zbus", false),
+            (b"\xff", false),
+        ] {
+            let socket = MemSocket::new()
+                .push_read(format!("\0AUTH EXTERNAL {}\r\nBEGIN\r\n", hex::encode(id)));
+            let written = socket.written();
+            let server =
+                Server::new(socket.into(), Guid::generate().into(), Some(0), None, None).unwrap();
+
+            let res = crate::utils::block_on(server.perform());
+            assert_eq!(res.is_ok(), accepted, "{id:?}");
+            if !accepted {
+                assert!(
+                    written
+                        .lock()
+                        .unwrap()
+                        .starts_with(b"REJECTED EXTERNAL\r\n"),
+                    "{id:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn credential_policy() {
+        // The identity, either sent right away or in reply to the `DATA` request of the server.
+        // Without one (empty `DATA`), the client is whoever its credentials say it is.
+        let id = hex::encode(sasl_auth_id().unwrap());
+        for (client_data, data_request) in [
+            (format!("\0AUTH EXTERNAL {id}\r\nBEGIN\r\n"), ""),
+            (
+                format!("\0AUTH EXTERNAL\r\nDATA {id}\r\nBEGIN\r\n"),
+                "DATA\r\n",
+            ),
+            (
+                "\0AUTH EXTERNAL\r\nDATA\r\nBEGIN\r\n".to_string(),
+                "DATA\r\n",
+            ),
+        ] {
+            for policy in [
+                CredentialPolicy::Required,
+                CredentialPolicy::TrustClaimed,
+                CredentialPolicy::Reject,
+            ] {
+                let socket = MemSocket::new().push_read(client_data.clone());
+                let written = socket.written();
+                let guid = OwnedGuid::from(Guid::generate());
+                let mut server =
+                    Server::new(socket.into(), guid.clone(), None, None, None).unwrap();
+                server.set_credential_policy(policy);
+
+                let res = crate::utils::block_on(server.perform());
+                let written = written.lock().unwrap();
+                let written = written
+                    .strip_prefix(data_request.as_bytes())
+                    .unwrap_or_else(|| panic!("{client_data:?}: no data request"));
+                match policy {
+                    CredentialPolicy::Required => {
+                        let err = res.unwrap_err();
+                        assert!(
+                            matches!(err, Error::Handshake(ref e) if e.starts_with("Peer credentials unavailable")),
+                            "{client_data:?}: unexpected error: {err}",
+                        );
+                        assert!(written.is_empty(), "{client_data:?}");
+                    }
+                    CredentialPolicy::TrustClaimed => {
+                        res.unwrap();
+                        assert_eq!(written, format!("OK {guid}\r\n").as_bytes());
+                    }
+                    CredentialPolicy::Reject => {
+                        res.unwrap_err();
+                        assert!(
+                            written.starts_with(b"REJECTED EXTERNAL\r\n"),
+                            "{client_data:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn skip_identity_check() {
+        // A client claiming to be someone else, or not even a UID.
+        for id in ["30", "7a627573"] {
+            for skip in [false, true] {
+                let socket =
+                    MemSocket::new().push_read(format!("\0AUTH EXTERNAL {id}\r\nBEGIN\r\n"));
+                let guid = OwnedGuid::from(Guid::generate());
+                let mut server = Server::new(socket.into(), guid, Some(1000), None, None).unwrap();
+                server.set_credential_policy(CredentialPolicy::TrustClaimed);
+                server.set_skip_identity_check(skip);
+
+                let res = crate::utils::block_on(server.perform());
+                assert_eq!(res.is_ok(), skip, "{id}");
+            }
+        }
+    }
+
+    // Recorded client data and the expected server response (or error) for each.
+    #[test]
+    #[timeout(15000)]
+    fn server_transcripts() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let id = hex::encode(sasl_auth_id().unwrap());
+        let cases: [(&str, Vec<String>, std::result::Result<String, &str>); 5] = [
+            (
+                "EXTERNAL",
+                vec![
+                    format!("\0AUTH EXTERNAL {id}\r\n"),
+                    "NEGOTIATE_UNIX_FD\r\n".into(),
+                    "BEGIN\r\n".into(),
+                ],
+                Ok(format!("OK {guid}\r\nAGREE_UNIX_FD\r\n")),
+            ),
+            (
+                "pipelined commands",
+                vec![format!(
+                    "\0AUTH EXTERNAL {id}\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n"
+                )],
+                Ok(format!("OK {guid}\r\nAGREE_UNIX_FD\r\n")),
+            ),
+            (
+                "REJECTED then retry",
+                vec![
+                    "\0AUTH ANONYMOUS\r\n".into(),
+                    format!("AUTH EXTERNAL {id}\r\nBEGIN\r\n"),
+                ],
+                Ok(format!("REJECTED EXTERNAL\r\nOK {guid}\r\n")),
+            ),
+            (
+                "missing NUL",
+                vec![format!("AUTH EXTERNAL {id}\r\nBEGIN\r\n")],
+                Err("First client byte is not NUL"),
+            ),
+            (
+                "EOF mid-handshake",
+                vec![format!("\0AUTH EXTERNAL {id}\r\n")],
+                Err("Unexpected EOF during handshake"),
+            ),
+        ];
+
+        for (name, reads, expected) in cases {
+            let socket = reads
+                .into_iter()
+                .fold(MemSocket::new(), |socket, data| socket.push_read(data));
+            let written = socket.written();
+            let server = Server::new(
+                socket.into(),
+                guid.clone(),
+                Some(Uid::effective().into()),
+                None,
+                None,
+            )
+            .unwrap();
+
+            let res = crate::utils::block_on(server.perform());
+            match (res, expected) {
+                (Ok(_), Ok(expected)) => {
+                    assert_eq!(*written.lock().unwrap(), expected.as_bytes(), "{name}")
+                }
+                (Err(e), Err(expected)) => {
+                    assert!(
+                        e.to_string().contains(expected),
+                        "{name}: unexpected error: {e}"
+                    )
+                }
+                (res, expected) => panic!("{name}: expected {expected:?}, got {res:?}"),
+            }
+        }
+    }
+
+    // Recorded server data and the expected client outcome for each.
+    #[test]
+    #[timeout(15000)]
+    fn client_transcripts() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let cases: [(&str, Vec<String>, std::result::Result<(), &str>); 4] = [
+            (
+                "EXTERNAL",
+                vec![format!("OK {guid}\r\n"), "AGREE_UNIX_FD\r\n".into()],
+                Ok(()),
+            ),
+            (
+                "pipelined commands",
+                vec![format!("OK {guid}\r\nAGREE_UNIX_FD\r\n")],
+                Ok(()),
+            ),
+            (
+                "bad GUID",
+                vec!["OK not-a-guid\r\n".into()],
+                Err("Invalid server GUID 'not-a-guid'"),
+            ),
+            (
+                "EOF mid-handshake",
+                vec![],
+                Err("Unexpected EOF during handshake"),
+            ),
+        ];
+
+        for (name, reads, expected) in cases {
+            let socket = reads
+                .into_iter()
+                .fold(MemSocket::new(), |socket, data| socket.push_read(data));
+            let client = Client::new(
+                socket.into(),
+                Some(AuthMechanism::External),
+                None,
+                None,
+                false,
+            );
+
+            let res = crate::utils::block_on(client.perform());
+            match (res, expected) {
+                (Ok(client), Ok(())) => assert_eq!(client.server_guid, guid, "{name}"),
+                (Err(e), Err(expected)) => {
+                    assert!(
+                        e.to_string().contains(expected),
+                        "{name}: unexpected error: {e}"
+                    )
+                }
+                (res, expected) => panic!("{name}: expected {expected:?}, got {res:?}"),
+            }
+        }
+    }
+
+    // Poor man's fuzzing: the server must never panic, whatever the client sends.
+    #[test]
+    #[timeout(15000)]
+    fn server_random_input() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        const FRAGMENTS: &[&[u8]] = &[
+            b"\0",
+            b"\r",
+            b"\n",
+            b"\r\n",
+            b" ",
+            b"AUTH",
+            b"EXTERNAL",
+            b"ANONYMOUS",
+            b"DATA",
+            b"BEGIN",
+            b"CANCEL",
+            b"ERROR",
+            b"NEGOTIATE_UNIX_FD",
+            b"31303030",
+            b"zz",
+        ];
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let mut data = Vec::new();
+            if rng.gen_bool(0.8) {
+                data.push(b'\0');
+            }
+            for _ in 0..rng.gen_range(0..16) {
+                if rng.gen_bool(0.8) {
+                    data.extend_from_slice(FRAGMENTS[rng.gen_range(0..FRAGMENTS.len())]);
+                } else {
+                    data.push(rng.gen());
+                }
+            }
+
+            // Also vary how the data is split across reads.
+            let mut socket = MemSocket::new();
+            let mut rest = &data[..];
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at(rng.gen_range(1..=rest.len()));
+                socket = socket.push_read(chunk);
+                rest = tail;
+            }
+            let mut server = Server::new(
+                socket.into(),
+                Guid::generate().into(),
+                Some(1000),
+                None,
+                None,
+            )
+            .unwrap();
+            server.set_lenient_leading_nul(rng.gen());
+            server.set_lenient_line_endings(rng.gen());
+
+            let _ = crate::utils::block_on(server.perform());
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn handshake_data_limit() {
+        let mut data = b"\0AUTH ANONYMOUS ".to_vec();
+        data.resize(65 * 1024, b'a');
+        let socket = MemSocket::new().push_read(data);
+        let server = Server::new(
+            socket.into(),
+            Guid::generate().into(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+
+        let err = crate::utils::block_on(server.perform()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "D-Bus handshake failed: Handshake data limit exceeded (during authentication)"
+        );
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn take_leftover() {
+        let socket = MemSocket::new().push_read("\0AUTH ANONYMOUS abcd\r\nBEGIN\r\nl\0ftover");
+        let server = Server::new(
+            socket.into(),
+            Guid::generate().into(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+
+        let mut server = crate::utils::block_on(server.perform()).unwrap();
+        assert_eq!(server.take_leftover(), b"l\0ftover");
+        assert!(server.take_leftover().is_empty());
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn partial_writes() {
+        let guid = OwnedGuid::from(Guid::generate());
+        for limit in [1, 2, 7] {
+            let socket = MemSocket::new()
+                .push_read(format!("OK {guid}\r\n"))
+                .push_read("AGREE_UNIX_FD\r\n")
+                .write_limit(limit);
+            let written = socket.written();
+            let client = Client::new(
+                socket.into(),
+                Some(AuthMechanism::External),
+                Some(b"1000".to_vec().into()),
+                None,
+                false,
+            );
+
+            crate::utils::block_on(client.perform()).unwrap();
+            assert_eq!(
+                *written.lock().unwrap(),
+                b"\0AUTH EXTERNAL 31303030\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n",
+                "write limit: {limit}",
+            );
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn unparsed_bytes_in_errors() {
+        for (data, expected) in [
+            (
+                "\0FOO bar\r\n",
+                r#"46 4f 4f 20 62 61 72 0d 0a ("FOO bar..")"#,
+            ),
+            (
+                "\0AUTH EXTERNAL\n",
+                r#"(got 00 41 55 54 48 20 45 58 54 45 52 4e 41 4c 0a (".AUTH EXTERNAL."))"#,
+            ),
+            // Malformed lines that still carry credentials.
+            (
+                "\0auth EXTERNAL 31303030\r\n",
+                r#"(got 61 75 74 68 20 45 58 54 45 52 4e 41 4c ("auth EXTERNAL") [REDACTED])"#,
+            ),
+            (
+                "\0AUTH\0EXTERNAL 31303030\r\n",
+                r#"(got 41 55 54 48 ("AUTH") [REDACTED])"#,
+            ),
+            (
+                "\0AUTH EXTERNAL31303030\r\n",
+                r#"(got 41 55 54 48 ("AUTH") [REDACTED])"#,
+            ),
+            (
+                "\0AUTH EXTERNAL 31303030",
+                r#"(unparsed 00 41 55 54 48 20 45 58 54 45 52 4e 41 4c (".AUTH EXTERNAL") [REDACTED])"#,
+            ),
+        ] {
+            let socket = MemSocket::new().push_read(data);
+            let guid = OwnedGuid::from(Guid::generate());
+            let server = Server::new(socket.into(), guid, Some(1000), None, None).unwrap();
+
+            let err = crate::utils::block_on(server.perform()).unwrap_err();
+            let err = err.to_string();
+            assert!(err.contains(expected), "unexpected error: {err}");
+            assert!(!err.contains("31 30"), "credentials leaked: {err}");
+            assert!(!err.contains("3130"), "credentials leaked: {err}");
+        }
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn not_ready_sockets() {
+        let guid = OwnedGuid::from(Guid::generate());
+        let socket = MemSocket::new()
+            .push_read(format!("OK {guid}\r\n"))
+            .push_read("AGREE_UNIX_FD\r\n")
+            .write_limit(3)
+            .not_ready_once();
+        let written = socket.written();
+        let client = Client::new(
+            socket.into(),
+            Some(AuthMechanism::External),
+            Some(b"1000".to_vec().into()),
+            None,
+            false,
+        );
+        let client = crate::utils::block_on(client.perform()).unwrap();
+        assert!(client.cap_unix_fd);
+        assert_eq!(
+            *written.lock().unwrap(),
+            b"\0AUTH EXTERNAL 31303030\r\nNEGOTIATE_UNIX_FD\r\nBEGIN\r\n",
+        );
+
+        let socket = MemSocket::new()
+            .push_read("\0AUTH EXTER")
+            .push_read("NAL 31303030\r\nNEGOTIATE_UNIX_FD\r")
+            .push_read("\nBEGIN\r\n")
+            .write_limit(3)
+            .not_ready_once();
+        let written = socket.written();
+        let server = Server::new(socket.into(), guid.clone(), Some(1000), None, None).unwrap();
+        let server = crate::utils::block_on(server.perform()).unwrap();
+        assert!(server.cap_unix_fd);
+        assert_eq!(
+            *written.lock().unwrap(),
+            format!("OK {guid}\r\nAGREE_UNIX_FD\r\n").as_bytes()
+        );
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn write_no_progress() {
+        let socket = MemSocket::new()
+            .push_read("\0AUTH ANONYMOUS abcd\r\nBEGIN\r\n")
+            .write_limit(0);
+        let server = Server::new(
+            socket.into(),
+            Guid::generate().into(),
+            Some(Uid::effective().into()),
+            Some(AuthMechanism::Anonymous),
+            None,
+        )
+        .unwrap();
+
+        let err = crate::utils::block_on(server.perform()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "D-Bus handshake failed: Write made no progress (during authentication)"
+        );
+    }
+
+    #[test]
+    #[timeout(15000)]
+    fn external_identity() {
+        let guid = OwnedGuid::from(Guid::generate());
+        for (id, expected) in [
+            (
+                ExternalIdentity::EffectiveUid,
+                hex::encode(Uid::effective().to_string()),
+            ),
+            (
+                ExternalIdentity::RealUid,
+                hex::encode(Uid::current().to_string()),
+            ),
+            (ExternalIdentity::Explicit(1000), "31303030".to_string()),
+            // root
+            (ExternalIdentity::Explicit(0), "30".to_string()),
+        ] {
+            assert_eq!(id.to_hex().unwrap(), expected);
+            let auth_line = id.auth_line().unwrap();
+            assert_eq!(
+                auth_line,
+                format!("\0AUTH EXTERNAL {expected}\r\n").as_bytes()
+            );
+            let socket = MemSocket::new()
+                .push_read(format!("OK {guid}\r\n"))
+                .push_read("AGREE_UNIX_FD\r\n");
+            let written = socket.written();
+            let client = Client::new(
+                socket.into(),
+                Some(AuthMechanism::External),
+                Some(id),
+                None,
+                false,
+            );
+
+            crate::utils::block_on(client.perform()).unwrap();
+            assert!(written.lock().unwrap().starts_with(&auth_line));
+        }
+    }
 }
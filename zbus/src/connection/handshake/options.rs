@@ -0,0 +1,25 @@
+#[cfg(feature = "p2p")]
+use super::CredentialPolicy;
+use super::TranscriptMode;
+
+/// Handshake settings that can be changed through the connection builder.
+///
+/// Everything is off by default, i.e. the handshake strictly follows the specification.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HandshakeOptions {
+    /// Tolerate non-standard server replies during UNIX FD negotiation (client only).
+    pub lenient_fd_negotiation: bool,
+    /// Accept lines terminated by a lone `\n` from the peer.
+    pub lenient_line_endings: bool,
+    /// Record a transcript of the handshake.
+    pub transcript: Option<TranscriptMode>,
+    /// What to do if the credentials of the client are unknown (server only).
+    #[cfg(feature = "p2p")]
+    pub credential_policy: CredentialPolicy,
+    /// Accept any identity the client claims (server only).
+    #[cfg(feature = "p2p")]
+    pub skip_identity_check: bool,
+    /// Tolerate extra NUL bytes before the first command of the client (server only).
+    #[cfg(feature = "p2p")]
+    pub lenient_leading_nul: bool,
+}
@@ -1,10 +1,11 @@
 use async_trait::async_trait;
-use tracing::{instrument, trace};
+use tracing::{debug, instrument, trace, warn};
 
 use crate::names::OwnedUniqueName;
 
 use super::{
-    AuthMechanism, Authenticated, BoxedSplit, Command, Common, Error, Handshake, OwnedGuid, Result,
+    step_error, AuthMechanism, Authenticated, BoxedSplit, Command, Common, CredentialPolicy, Error,
+    Handshake, OwnedGuid, Result, TranscriptMode,
 };
 
 /*
@@ -19,6 +20,19 @@ enum ServerHandshakeStep {
     Done,
 }
 
+impl ServerHandshakeStep {
+    // The phase of the handshake this step belongs to, as named in errors on both sides.
+    fn phase(&self) -> &'static str {
+        match self {
+            ServerHandshakeStep::WaitingForAuth | ServerHandshakeStep::WaitingForData(_) => {
+                "authentication"
+            }
+            ServerHandshakeStep::WaitingForBegin => "negotiation",
+            ServerHandshakeStep::Done => "completion",
+        }
+    }
+}
+
 /// A representation of an in-progress handshake, server-side
 ///
 /// This would typically be used to implement a D-Bus broker, or in the context of a P2P connection.
@@ -32,6 +46,8 @@ pub struct Server {
     #[cfg(windows)]
     client_sid: Option<String>,
     unique_name: Option<OwnedUniqueName>,
+    credential_policy: CredentialPolicy,
+    skip_identity_check: bool,
 }
 
 impl Server {
@@ -54,9 +70,38 @@ impl Server {
             client_sid,
             guid,
             unique_name,
+            credential_policy: CredentialPolicy::default(),
+            skip_identity_check: false,
         })
     }
 
+    /// Record all the lines exchanged during the handshake.
+    pub fn record_transcript(&mut self, mode: TranscriptMode) {
+        self.common.record_transcript(mode);
+    }
+
+    /// Tolerate extra NUL bytes before the first command of the client.
+    ///
+    /// By default, the client must send exactly one NUL byte before its first command.
+    pub fn set_lenient_leading_nul(&mut self, lenient: bool) {
+        self.common.set_lenient_leading_nul(lenient);
+    }
+
+    /// Accept lines terminated by a lone `\n` from the client, rather than only `\r\n`.
+    pub fn set_lenient_line_endings(&mut self, lenient: bool) {
+        self.common.set_lenient_line_endings(lenient);
+    }
+
+    /// Set what to do if the credentials of the client are unknown.
+    pub fn set_credential_policy(&mut self, policy: CredentialPolicy) {
+        self.credential_policy = policy;
+    }
+
+    /// Accept any identity the client claims with the `EXTERNAL` mechanism, without verifying it.
+    pub fn set_skip_identity_check(&mut self, skip: bool) {
+        self.skip_identity_check = skip;
+    }
+
     #[instrument(skip(self))]
     async fn auth_ok(&mut self) -> Result<()> {
         let guid = self.guid.clone();
@@ -69,26 +114,67 @@ impl Server {
     }
 
     async fn check_external_auth(&mut self, sasl_id: &[u8]) -> Result<()> {
-        let auth_ok = {
-            let id = std::str::from_utf8(sasl_id)
-                .map_err(|e| Error::Handshake(format!("Invalid ID: {e}")))?;
+        if self.skip_identity_check {
+            debug!("Accepting EXTERNAL authentication without checking the client identity");
+
+            return self.auth_ok().await;
+        }
+
+        // The identity is an opaque byte string as far as SASL is concerned, so one we can't make
+        // sense of (e.g. from a client using a custom identity) is rejected like a mismatch, to let
+        // the client try another mechanism.
+        let Ok(id) = std::str::from_utf8(sasl_id) else {
+            return self.rejected_error("identity is not UTF-8").await;
+        };
+        let id_matches = {
             #[cfg(unix)]
             {
-                let uid = id
-                    .parse::<u32>()
-                    .map_err(|e| Error::Handshake(format!("Invalid UID: {e}")))?;
-                self.client_uid.map(|u| u == uid).unwrap_or(false)
+                // `u32::from_str` also accepts a leading `+`, unlike the decimal digits the spec
+                // expects. Leading zeros are fine though, as with dbus-daemon.
+                if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+                    return self.rejected_error("identity is not a UID").await;
+                }
+                // `-1` as `uid_t` means "no UID" and can't be a real user.
+                let Some(uid) = id.parse::<u32>().ok().filter(|uid| *uid != u32::MAX) else {
+                    return self.rejected_error("invalid UID value").await;
+                };
+                self.client_uid.map(|u| u == uid)
             }
             #[cfg(windows)]
             {
-                self.client_sid.as_ref().map(|u| u == id).unwrap_or(false)
+                self.client_sid.as_ref().map(|u| u == id)
             }
         };
 
-        if auth_ok {
-            self.auth_ok().await
-        } else {
-            self.rejected_error().await
+        self.apply_credential_policy(id_matches).await
+    }
+
+    // Accept the client without an explicit identity, i.e. as whoever its credentials say it is.
+    async fn check_implicit_external_auth(&mut self) -> Result<()> {
+        if self.skip_identity_check {
+            debug!("Accepting EXTERNAL authentication without checking the client identity");
+
+            return self.auth_ok().await;
+        }
+
+        #[cfg(unix)]
+        let known = self.client_uid.is_some();
+        #[cfg(windows)]
+        let known = self.client_sid.is_some();
+        self.apply_credential_policy(known.then_some(true)).await
+    }
+
+    // `id_matches` is `None` if the credentials of the client are unknown.
+    async fn apply_credential_policy(&mut self, id_matches: Option<bool>) -> Result<()> {
+        match (id_matches, self.credential_policy) {
+            (Some(true), _) | (None, CredentialPolicy::TrustClaimed) => self.auth_ok().await,
+            (Some(false), _) => self.rejected_error("identity mismatch").await,
+            (None, CredentialPolicy::Reject) => {
+                self.rejected_error("peer credentials unavailable").await
+            }
+            (None, CredentialPolicy::Required) => Err(Error::Handshake(
+                "Peer credentials unavailable for EXTERNAL authentication".to_string(),
+            )),
         }
     }
 
@@ -101,8 +187,26 @@ impl Server {
     }
 
     #[instrument(skip(self))]
-    async fn rejected_error(&mut self) -> Result<()> {
-        let cmd = Command::Rejected(self.common.mechanism().as_str().into());
+    async fn rejected_error(&mut self, reason: &str) -> Result<()> {
+        let mech = self.common.mechanism();
+        // Structured fields so rejections can be filtered on, e.g. in the journal with
+        // `tracing-journald` (which prefixes the field names, unless told otherwise). Only `debug`
+        // since rejecting a mechanism and letting the client try another one is routine.
+        #[cfg(unix)]
+        debug!(
+            dbus_auth_mech = %mech,
+            dbus_peer_uid = self.client_uid,
+            dbus_reject_reason = reason,
+            "Rejecting client authentication"
+        );
+        #[cfg(windows)]
+        debug!(
+            dbus_auth_mech = %mech,
+            dbus_peer_sid = self.client_sid.as_deref(),
+            dbus_reject_reason = reason,
+            "Rejecting client authentication"
+        );
+        let cmd = Command::Rejected(mech.as_str().into());
         trace!("Sending authentication error");
         self.common.write_command(cmd).await?;
         self.step = ServerHandshakeStep::WaitingForAuth;
@@ -134,7 +238,7 @@ impl Server {
             Command::Auth(requested_mech, resp) => {
                 let mech = self.common.mechanism();
                 if requested_mech != Some(mech) {
-                    self.rejected_error().await?;
+                    self.rejected_error("unsupported mechanism").await?;
 
                     return Ok(());
                 }
@@ -153,7 +257,7 @@ impl Server {
             }
             Command::Cancel | Command::Error(_) => {
                 trace!("Received CANCEL or ERROR command from the client");
-                self.rejected_error().await?;
+                self.rejected_error("cancelled by the client").await?;
             }
             _ => self.unsupported_command_error().await?,
         }
@@ -169,7 +273,9 @@ impl Server {
         trace!("Waiting for authentication data");
         let reply = self.common.read_command().await?;
         match (mech, reply) {
-            (AuthMechanism::External, Command::Data(None)) => self.auth_ok().await?,
+            (AuthMechanism::External, Command::Data(None)) => {
+                self.check_implicit_external_auth().await?;
+            }
             (AuthMechanism::External, Command::Data(Some(data))) => {
                 self.check_external_auth(&data).await?;
             }
@@ -189,21 +295,35 @@ impl Server {
         match reply {
             Command::Begin => {
                 trace!("Received Begin command from the client");
+                #[cfg(unix)]
+                if !self.common.cap_unix_fd() && self.common.socket().read().can_pass_unix_fd() {
+                    // Perfectly valid but worth knowing about when debugging FD-passing issues.
+                    debug!("Client did not negotiate UNIX file descriptor passing before BEGIN");
+                }
                 self.step = ServerHandshakeStep::Done;
             }
             Command::Cancel | Command::Error(_) => {
                 trace!("Received CANCEL or ERROR command from the client");
-                self.rejected_error().await?;
+                self.rejected_error("cancelled by the client").await?;
+            }
+            // Never the case on non-Unix platforms, since FD passing is never agreed there.
+            Command::NegotiateUnixFD if self.common.cap_unix_fd() => {
+                warn!("Client sent NEGOTIATE_UNIX_FD again after it was agreed");
+                let cmd = Command::Error("UNIX FD passing already negotiated".to_string());
+                self.common.write_command(cmd).await?;
             }
             #[cfg(unix)]
             Command::NegotiateUnixFD => {
                 trace!("Received NEGOTIATE_UNIX_FD command from the client");
+                self.common.set_unix_fd_requested();
                 if self.common.socket().read().can_pass_unix_fd() {
                     self.common.set_cap_unix_fd(true);
                     trace!("Sending AGREE_UNIX_FD to the client");
                     self.common.write_command(Command::AgreeUnixFD).await?;
                 } else {
-                    trace!("FD transmission not possible on this socket type. Rejecting..");
+                    // Logged at the same level as a client not asking, so the two cases can be
+                    // told apart when debugging FD-passing issues.
+                    debug!("Client requested UNIX FD passing but this socket type can't pass FDs");
                     let cmd =
                         Command::Error("FD-passing not possible on this socket type".to_string());
                     self.common.write_command(cmd).await?;
@@ -220,13 +340,37 @@ impl Server {
 impl Handshake for Server {
     #[instrument(skip(self))]
     async fn perform(mut self) -> Result<Authenticated> {
-        while !self.next_step().await? {}
+        loop {
+            let phase = self.step.phase();
+            if self.next_step().await.map_err(|e| step_error(phase, e))? {
+                break;
+            }
+        }
 
-        trace!("Handshake done");
+        let transcript = self.common.take_transcript();
+        #[cfg(unix)]
+        let unix_fd_requested = self.common.unix_fd_requested();
         #[cfg(unix)]
-        let (socket, recv_buffer, received_fds, cap_unix_fd, _) = self.common.into_components();
+        let (socket, recv_buffer, received_fds, cap_unix_fd, mechanism) =
+            self.common.into_components();
         #[cfg(not(unix))]
-        let (socket, recv_buffer, _, _) = self.common.into_components();
+        let (socket, recv_buffer, cap_unix_fd, mechanism) = self.common.into_components();
+        #[cfg(unix)]
+        debug!(
+            dbus_auth_mech = %mechanism,
+            dbus_cap_unix_fd = cap_unix_fd,
+            dbus_server_guid = %self.guid,
+            dbus_peer_uid = self.client_uid,
+            "Handshake done"
+        );
+        #[cfg(windows)]
+        debug!(
+            dbus_auth_mech = %mechanism,
+            dbus_cap_unix_fd = cap_unix_fd,
+            dbus_server_guid = %self.guid,
+            dbus_peer_sid = self.client_sid.as_deref(),
+            "Handshake done"
+        );
         let (read, write) = socket.take();
         Ok(Authenticated {
             socket_write: write,
@@ -234,10 +378,13 @@ impl Handshake for Server {
             server_guid: self.guid,
             #[cfg(unix)]
             cap_unix_fd,
+            #[cfg(unix)]
+            unix_fd_requested,
             already_received_bytes: recv_buffer,
             #[cfg(unix)]
             already_received_fds: received_fds,
             unique_name: self.unique_name,
+            transcript,
         })
     }
 }
@@ -0,0 +1,85 @@
+use std::time::SystemTime;
+
+use super::Command;
+
+/// The direction of a [`TranscriptEntry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscriptDirection {
+    /// The line was sent to the peer.
+    Sent,
+    /// The line was received from the peer.
+    Received,
+}
+
+/// Whether authentication data is kept in a handshake transcript.
+///
+/// See [`crate::connection::Builder::handshake_transcript`] for details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscriptMode {
+    /// Record every line as is.
+    Full,
+    /// Replace the authentication data (e.g. the identity sent with `EXTERNAL`) with
+    /// `[REDACTED]`.
+    Redacted,
+}
+
+/// A single line exchanged during the authentication handshake.
+///
+/// See [`crate::connection::Builder::handshake_transcript`] for details.
+#[derive(Clone, Debug)]
+pub struct TranscriptEntry {
+    direction: TranscriptDirection,
+    timestamp: SystemTime,
+    line: String,
+}
+
+impl TranscriptEntry {
+    /// Whether the line was sent or received.
+    pub fn direction(&self) -> TranscriptDirection {
+        self.direction
+    }
+
+    /// When the line was sent or received.
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    /// The line, without the `\r\n` terminator.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+}
+
+// Recorder of all the commands exchanged during a handshake.
+#[derive(Debug)]
+pub(super) struct Transcript {
+    entries: Vec<TranscriptEntry>,
+    redact: bool,
+}
+
+impl Transcript {
+    pub fn new(mode: TranscriptMode) -> Self {
+        Self {
+            entries: Vec::new(),
+            redact: mode == TranscriptMode::Redacted,
+        }
+    }
+
+    pub fn record(&mut self, direction: TranscriptDirection, command: &Command, line: &str) {
+        let line = match command {
+            Command::Auth(Some(mech), Some(_)) if self.redact => format!("AUTH {mech} [REDACTED]"),
+            Command::Data(Some(_)) if self.redact => "DATA [REDACTED]".to_string(),
+            _ => line.to_string(),
+        };
+
+        self.entries.push(TranscriptEntry {
+            direction,
+            timestamp: SystemTime::now(),
+            line,
+        });
+    }
+
+    pub fn into_entries(self) -> Vec<TranscriptEntry> {
+        self.entries
+    }
+}
@@ -39,8 +39,12 @@ mod socket_reader;
 use socket_reader::SocketReader;
 
 pub(crate) mod handshake;
-pub use handshake::AuthMechanism;
 use handshake::Authenticated;
+#[cfg(feature = "p2p")]
+pub use handshake::CredentialPolicy;
+pub use handshake::{
+    AuthMechanism, ExternalIdentity, TranscriptDirection, TranscriptEntry, TranscriptMode,
+};
 
 mod connect;
 
@@ -53,8 +57,11 @@ pub(crate) struct ConnectionInner {
     server_guid: OwnedGuid,
     #[cfg(unix)]
     cap_unix_fd: bool,
+    #[cfg(unix)]
+    unix_fd_requested: bool,
     #[cfg(feature = "p2p")]
     bus_conn: bool,
+    handshake_transcript: std::sync::Mutex<Vec<TranscriptEntry>>,
     unique_name: OnceLock<OwnedUniqueName>,
     registered_names: Mutex<HashMap<WellKnownName<'static>, NameStatus>>,
 
@@ -291,9 +298,12 @@ impl OrderedFuture for PendingMethodCall {
 
 impl Connection {
     /// Send `msg` to the peer.
+    ///
+    /// On Unix, this fails with [`Error::Unsupported`] if `msg` carries more file descriptors than
+    /// [`Connection::max_fds_per_message`] allows.
     pub async fn send(&self, msg: &Message) -> Result<()> {
         #[cfg(unix)]
-        if !msg.data().fds().is_empty() && !self.inner.cap_unix_fd {
+        if msg.data().fds().len() > self.max_fds_per_message() {
             return Err(Error::Unsupported);
         }
 
@@ -849,6 +859,45 @@ impl Connection {
         &self.inner.server_guid
     }
 
+    /// The maximum number of file descriptors that can be sent in a single message.
+    ///
+    /// This is `0` if file descriptor passing was not negotiated during the handshake. Otherwise,
+    /// it's the number of file descriptors Linux allows to be passed in one go, which is also used
+    /// on other Unix platforms. [`Connection::send`] rejects messages that exceed this limit.
+    #[cfg(unix)]
+    pub fn max_fds_per_message(&self) -> usize {
+        if self.inner.cap_unix_fd {
+            crate::utils::SCM_MAX_FD
+        } else {
+            0
+        }
+    }
+
+    /// Whether file descriptor passing was requested during the handshake.
+    ///
+    /// On the client side, this tells if `NEGOTIATE_UNIX_FD` was sent and on the server side, if
+    /// it was received from the client. Combined with [`Connection::max_fds_per_message`], it
+    /// tells a peer that didn't ask for file descriptor passing apart from one whose request was
+    /// declined.
+    #[cfg(unix)]
+    pub fn unix_fd_requested(&self) -> bool {
+        self.inner.unix_fd_requested
+    }
+
+    /// Take the transcript of the authentication handshake.
+    ///
+    /// The transcript is only recorded if requested through [`Builder::handshake_transcript`].
+    /// Otherwise, or on subsequent calls, an empty list is returned.
+    pub fn take_handshake_transcript(&self) -> Vec<TranscriptEntry> {
+        std::mem::take(
+            &mut self
+                .inner
+                .handshake_transcript
+                .lock()
+                .expect("lock poisoned"),
+        )
+    }
+
     /// The underlying executor.
     ///
     /// When a connection is built with internal_executor set to false, zbus will not spawn a
@@ -1152,8 +1201,11 @@ impl Connection {
                 server_guid: auth.server_guid,
                 #[cfg(unix)]
                 cap_unix_fd,
+                #[cfg(unix)]
+                unix_fd_requested: auth.unix_fd_requested,
                 #[cfg(feature = "p2p")]
                 bus_conn: bus_connection,
+                handshake_transcript: std::sync::Mutex::new(auth.transcript),
                 unique_name: OnceLock::new(),
                 subscriptions,
                 object_server: OnceLock::new(),
@@ -1199,7 +1251,9 @@ impl Connection {
     ///
     /// # Caveats
     ///
-    /// Currently `unix_group_ids` and `linux_security_label` fields are not populated.
+    /// Currently the `unix_group_ids` field is not populated and `linux_security_label` is only
+    /// populated on Linux, for Unix sockets, when a Linux Security Module (e.g. SELinux or
+    /// AppArmor) is enabled.
     pub async fn peer_credentials(&self) -> io::Result<ConnectionCredentials> {
         self.inner
             .socket_write
@@ -1688,6 +1742,166 @@ mod p2p_tests {
         )
     }
 
+    #[cfg(unix)]
+    #[test]
+    #[timeout(15000)]
+    fn unix_fd_p2p() {
+        crate::utils::block_on(test_unix_fd_p2p()).unwrap();
+    }
+
+    #[cfg(unix)]
+    async fn test_unix_fd_p2p() -> Result<()> {
+        use std::os::{
+            fd::{AsFd, OwnedFd},
+            unix::net::UnixStream,
+        };
+
+        let guid = Guid::generate();
+        let (p0, p1) = UnixStream::pair().unwrap();
+
+        let (client, server) = futures_util::try_join!(
+            Builder::unix_fd(OwnedFd::from(p1)).p2p().build(),
+            Builder::unix_fd(OwnedFd::from(p0))
+                .server(guid)
+                .unwrap()
+                .p2p()
+                .build(),
+        )?;
+        assert_eq!(client.server_guid(), server.server_guid());
+        assert!(server.peer_credentials().await?.is_same_user_as_current());
+        assert_eq!(client.max_fds_per_message(), 253);
+        assert!(client.unix_fd_requested());
+        assert!(server.unix_fd_requested());
+
+        let stdout = std::io::stdout();
+        let fds = (0..254)
+            .map(|_| stdout.as_fd().try_clone_to_owned())
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        let fds: Vec<_> = fds.iter().map(zvariant::Fd::from).collect();
+        let msg = Message::method_call("/", "do")?.build(&fds)?;
+        assert!(matches!(client.send(&msg).await, Err(Error::Unsupported)));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[timeout(15000)]
+    fn unix_fd_not_stream() {
+        use std::os::{fd::OwnedFd, unix::net::UnixDatagram};
+
+        let (p0, _p1) = UnixDatagram::pair().unwrap();
+        let err =
+            crate::utils::block_on(Builder::unix_fd(OwnedFd::from(p0)).p2p().build()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "D-Bus handshake failed: socket is not a stream socket"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[timeout(15000)]
+    fn handshake_timeout() {
+        crate::utils::block_on(test_handshake_timeout());
+    }
+
+    #[cfg(unix)]
+    async fn test_handshake_timeout() {
+        use std::os::{fd::OwnedFd, unix::net::UnixStream};
+
+        // The peer never replies.
+        let (p0, _p1) = UnixStream::pair().unwrap();
+        let err = Builder::unix_fd(OwnedFd::from(p0))
+            .p2p()
+            .handshake_timeout(std::time::Duration::from_millis(10))
+            .build()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::Handshake(ref e) if e == "Timed out"),
+            "unexpected error: {err}",
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[timeout(15000)]
+    fn stream_socket_p2p() {
+        crate::utils::block_on(test_stream_socket_p2p()).unwrap();
+    }
+
+    #[cfg(unix)]
+    async fn test_stream_socket_p2p() -> Result<()> {
+        use super::socket::StreamSocket;
+
+        let guid = Guid::generate();
+        #[cfg(not(feature = "tokio"))]
+        let (p0, p1) = async_io::Async::<std::os::unix::net::UnixStream>::pair().unwrap();
+        #[cfg(feature = "tokio")]
+        let (p0, p1) = tokio::net::UnixStream::pair().unwrap();
+
+        let (client, server) = futures_util::try_join!(
+            Builder::socket(StreamSocket::new(p1)).p2p().build(),
+            Builder::socket(StreamSocket::new(p0))
+                .server(guid)
+                .unwrap()
+                .p2p()
+                .build(),
+        )?;
+        assert_eq!(client.server_guid(), server.server_guid());
+        assert_eq!(client.max_fds_per_message(), 0);
+        assert!(!server.unix_fd_requested());
+
+        let mut stream = MessageStream::from(&server);
+        client
+            .emit_signal(None::<()>, "/", "org.zbus.Test", "Test", &())
+            .await?;
+        let msg = stream.try_next().await?.unwrap();
+        assert_eq!(msg.header().member().unwrap(), "Test");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[timeout(15000)]
+    fn reused_peer_credentials() {
+        crate::utils::block_on(test_reused_peer_credentials()).unwrap();
+    }
+
+    #[cfg(unix)]
+    async fn test_reused_peer_credentials() -> Result<()> {
+        use super::socket::StreamSocket;
+
+        let (server, _client) = unix_p2p_pipe().await?;
+        let creds = server.peer_credentials().await?;
+
+        // A generic stream can't provide the peer credentials so EXTERNAL authentication only
+        // succeeds with the credentials of the first connection.
+        #[cfg(not(feature = "tokio"))]
+        let (p0, p1) = async_io::Async::<std::os::unix::net::UnixStream>::pair().unwrap();
+        #[cfg(feature = "tokio")]
+        let (p0, p1) = tokio::net::UnixStream::pair().unwrap();
+        let (client, server) = futures_util::try_join!(
+            Builder::socket(StreamSocket::new(p1))
+                .auth_mechanism(AuthMechanism::External)
+                .p2p()
+                .build(),
+            Builder::socket(StreamSocket::new(p0))
+                .server(Guid::generate())
+                .unwrap()
+                .auth_mechanism(AuthMechanism::External)
+                .peer_credentials(creds)
+                .p2p()
+                .build(),
+        )?;
+        assert_eq!(client.server_guid(), server.server_guid());
+
+        Ok(())
+    }
+
     #[cfg(any(
         all(feature = "vsock", not(feature = "tokio")),
         feature = "tokio-vsock"
@@ -0,0 +1,166 @@
+use std::{
+    collections::VecDeque,
+    future::poll_fn,
+    io,
+    os::fd::BorrowedFd,
+    sync::{Arc, Mutex},
+    task::Poll,
+};
+
+use super::{ReadHalf, RecvmsgResult, Socket, Split, WriteHalf};
+
+/// An in-memory socket for driving the handshake in tests.
+///
+/// Every chunk queued with [`MemSocket::push_read`] is handed out by a separate `recvmsg` call, so
+/// tests have full control over how the peer's data is packetized. Once all chunks are consumed,
+/// reads return `0` (EOF). Everything written to the socket is recorded and can be inspected
+/// through the handle returned by [`MemSocket::written`].
+#[derive(Debug, Default)]
+pub(crate) struct MemSocket {
+    reads: VecDeque<Vec<u8>>,
+    written: Arc<Mutex<Vec<u8>>>,
+    write_limit: Option<usize>,
+    no_fd_passing: bool,
+    not_ready_once: bool,
+}
+
+impl MemSocket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `data` to be returned by a single `recvmsg` call.
+    pub fn push_read(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.reads.push_back(data.into());
+
+        self
+    }
+
+    /// Accept at most `limit` bytes per `sendmsg` call.
+    pub fn write_limit(mut self, limit: usize) -> Self {
+        self.write_limit = Some(limit);
+
+        self
+    }
+
+    /// Pretend to be a transport that can't pass file descriptors (e.g. TCP).
+    pub fn no_fd_passing(mut self) -> Self {
+        self.no_fd_passing = true;
+
+        self
+    }
+
+    /// Make every `recvmsg` and `sendmsg` call return `Pending` once before completing, like a
+    /// socket that isn't ready yet.
+    pub fn not_ready_once(mut self) -> Self {
+        self.not_ready_once = true;
+
+        self
+    }
+
+    /// A shared handle to all the bytes written to the socket.
+    pub fn written(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.written.clone()
+    }
+}
+
+impl Socket for MemSocket {
+    type ReadHalf = MemReadHalf;
+    type WriteHalf = MemWriteHalf;
+
+    fn split(self) -> Split<Self::ReadHalf, Self::WriteHalf> {
+        Split {
+            read: MemReadHalf {
+                reads: self.reads,
+                no_fd_passing: self.no_fd_passing,
+                not_ready_once: self.not_ready_once,
+            },
+            write: MemWriteHalf {
+                written: self.written,
+                write_limit: self.write_limit,
+                no_fd_passing: self.no_fd_passing,
+                not_ready_once: self.not_ready_once,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MemReadHalf {
+    reads: VecDeque<Vec<u8>>,
+    no_fd_passing: bool,
+    not_ready_once: bool,
+}
+
+#[async_trait::async_trait]
+impl ReadHalf for MemReadHalf {
+    async fn recvmsg(&mut self, buf: &mut [u8]) -> RecvmsgResult {
+        if self.not_ready_once {
+            pending_once().await;
+        }
+        let Some(mut chunk) = self.reads.pop_front() else {
+            return Ok((0, vec![]));
+        };
+        if chunk.len() > buf.len() {
+            let rest = chunk.split_off(buf.len());
+            self.reads.push_front(rest);
+        }
+        buf[..chunk.len()].copy_from_slice(&chunk);
+
+        Ok((chunk.len(), vec![]))
+    }
+
+    fn can_pass_unix_fd(&self) -> bool {
+        !self.no_fd_passing
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MemWriteHalf {
+    written: Arc<Mutex<Vec<u8>>>,
+    write_limit: Option<usize>,
+    no_fd_passing: bool,
+    not_ready_once: bool,
+}
+
+#[async_trait::async_trait]
+impl WriteHalf for MemWriteHalf {
+    async fn sendmsg(&mut self, buffer: &[u8], _fds: &[BorrowedFd<'_>]) -> io::Result<usize> {
+        if self.not_ready_once {
+            pending_once().await;
+        }
+        let len = self
+            .write_limit
+            .map_or(buffer.len(), |limit| buffer.len().min(limit));
+        self.written
+            .lock()
+            .unwrap()
+            .extend_from_slice(&buffer[..len]);
+
+        Ok(len)
+    }
+
+    async fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn can_pass_unix_fd(&self) -> bool {
+        !self.no_fd_passing
+    }
+}
+
+// Return `Pending` once, waking the task right away.
+async fn pending_once() {
+    let mut pending = true;
+    poll_fn(|cx| {
+        if pending {
+            pending = false;
+            cx.waker().wake_by_ref();
+
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await
+}
@@ -5,6 +5,11 @@ pub use channel::Channel;
 
 mod split;
 pub use split::{BoxedSplit, Split};
+mod stream;
+pub use stream::{StreamSocket, StreamSocketReadHalf, StreamSocketWriteHalf};
+
+#[cfg(all(test, unix, feature = "p2p"))]
+pub(crate) mod memory;
 
 pub(crate) mod tcp;
 pub(crate) mod unix;
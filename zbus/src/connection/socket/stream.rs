@@ -0,0 +1,152 @@
+use std::io;
+#[cfg(unix)]
+use std::os::fd::BorrowedFd;
+
+#[cfg(not(feature = "tokio"))]
+use futures_util::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::{ReadHalf, RecvmsgResult, Socket, Split, WriteHalf};
+use crate::conn::AuthMechanism;
+
+/// A socket over a generic byte stream.
+///
+/// This allows a D-Bus connection to be established over any bidirectional byte stream (e.g a
+/// channel of an SSH session or a WebSocket), not just the transports zbus supports natively.
+/// Since there is no way to pass file descriptors over a generic stream, FD passing is disabled.
+/// Neither can the peer credentials be obtained from it, so the `ANONYMOUS` mechanism is used for
+/// authentication by default.
+///
+/// The stream must implement the `AsyncRead` and `AsyncWrite` traits of `futures-io` or, when the
+/// `tokio` feature is enabled, those of `tokio`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[cfg(not(feature = "tokio"))]
+/// # {
+/// use zbus::connection::{socket::StreamSocket, Builder};
+///
+/// # zbus::block_on(async {
+/// // Any type implementing `AsyncRead` and `AsyncWrite` will do.
+/// let stream = async_io::Async::<std::net::TcpStream>::connect(([127, 0, 0, 1], 4242)).await?;
+/// let _conn = Builder::socket(StreamSocket::new(stream)).build().await?;
+/// # Ok::<(), zbus::Error>(())
+/// # }).unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct StreamSocket<T> {
+    stream: T,
+}
+
+impl<T> StreamSocket<T> {
+    /// Create a socket over `stream`.
+    pub fn new(stream: T) -> Self {
+        Self { stream }
+    }
+
+    /// Get back the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+}
+
+#[cfg(not(feature = "tokio"))]
+type StreamReadHalf<T> = futures_util::io::ReadHalf<T>;
+#[cfg(not(feature = "tokio"))]
+type StreamWriteHalf<T> = futures_util::io::WriteHalf<T>;
+#[cfg(feature = "tokio")]
+type StreamReadHalf<T> = tokio::io::ReadHalf<T>;
+#[cfg(feature = "tokio")]
+type StreamWriteHalf<T> = tokio::io::WriteHalf<T>;
+
+impl<T> Socket for StreamSocket<T>
+where
+    T: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Sync + Unpin + 'static,
+{
+    type ReadHalf = StreamSocketReadHalf<T>;
+    type WriteHalf = StreamSocketWriteHalf<T>;
+
+    fn split(self) -> Split<Self::ReadHalf, Self::WriteHalf> {
+        #[cfg(not(feature = "tokio"))]
+        let (read, write) = futures_util::io::AsyncReadExt::split(self.stream);
+        #[cfg(feature = "tokio")]
+        let (read, write) = tokio::io::split(self.stream);
+
+        Split {
+            read: StreamSocketReadHalf(read),
+            write: StreamSocketWriteHalf(write),
+        }
+    }
+}
+
+/// The read half of a [`StreamSocket`].
+#[derive(Debug)]
+pub struct StreamSocketReadHalf<T>(StreamReadHalf<T>);
+
+#[async_trait::async_trait]
+impl<T> ReadHalf for StreamSocketReadHalf<T>
+where
+    T: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Sync + Unpin + 'static,
+{
+    async fn recvmsg(&mut self, buf: &mut [u8]) -> RecvmsgResult {
+        #[cfg(not(feature = "tokio"))]
+        let len = futures_util::AsyncReadExt::read(&mut self.0, buf).await?;
+        #[cfg(feature = "tokio")]
+        let len = tokio::io::AsyncReadExt::read(&mut self.0, buf).await?;
+
+        #[cfg(unix)]
+        let ret = (len, vec![]);
+        #[cfg(not(unix))]
+        let ret = len;
+        Ok(ret)
+    }
+
+    fn auth_mechanism(&self) -> AuthMechanism {
+        AuthMechanism::Anonymous
+    }
+}
+
+/// The write half of a [`StreamSocket`].
+#[derive(Debug)]
+pub struct StreamSocketWriteHalf<T>(StreamWriteHalf<T>);
+
+#[async_trait::async_trait]
+impl<T> WriteHalf for StreamSocketWriteHalf<T>
+where
+    T: AsyncRead + AsyncWrite + std::fmt::Debug + Send + Sync + Unpin + 'static,
+{
+    async fn sendmsg(
+        &mut self,
+        buf: &[u8],
+        #[cfg(unix)] fds: &[BorrowedFd<'_>],
+    ) -> io::Result<usize> {
+        #[cfg(unix)]
+        if !fds.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "fds cannot be sent over a generic stream",
+            ));
+        }
+
+        #[cfg(not(feature = "tokio"))]
+        let len = futures_util::AsyncWriteExt::write(&mut self.0, buf).await?;
+        #[cfg(feature = "tokio")]
+        let len = tokio::io::AsyncWriteExt::write(&mut self.0, buf).await?;
+
+        Ok(len)
+    }
+
+    async fn close(&mut self) -> io::Result<()> {
+        #[cfg(not(feature = "tokio"))]
+        {
+            futures_util::AsyncWriteExt::close(&mut self.0).await
+        }
+        #[cfg(feature = "tokio")]
+        {
+            tokio::io::AsyncWriteExt::shutdown(&mut self.0).await
+        }
+    }
+}
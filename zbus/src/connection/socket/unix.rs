@@ -337,13 +337,18 @@ fn get_unix_peer_creds_blocking(fd: RawFd) -> io::Result<crate::fdo::ConnectionC
     {
         use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
 
-        getsockopt(&fd, PeerCredentials)
-            .map(|creds| {
-                crate::fdo::ConnectionCredentials::default()
-                    .set_process_id(creds.pid() as _)
-                    .set_unix_user_id(creds.uid())
-            })
-            .map_err(|e| e.into())
+        let creds = getsockopt(&fd, PeerCredentials).map(|creds| {
+            crate::fdo::ConnectionCredentials::default()
+                .set_process_id(creds.pid() as _)
+                .set_unix_user_id(creds.uid())
+        })?;
+
+        // The security label is only available if an LSM is enabled so it's not an error if we
+        // fail to get it.
+        Ok(match get_peer_security_label(fd) {
+            Ok(label) => creds.set_linux_security_label(label),
+            Err(_) => creds,
+        })
     }
 
     #[cfg(any(
@@ -361,6 +366,46 @@ fn get_unix_peer_creds_blocking(fd: RawFd) -> io::Result<crate::fdo::ConnectionC
     }
 }
 
+// Get the LSM (e.g SELinux or AppArmor) security label of the peer through `SO_PEERSEC`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn get_peer_security_label(fd: BorrowedFd<'_>) -> io::Result<Vec<u8>> {
+    use nix::libc;
+
+    let mut label = vec![0u8; 256];
+    loop {
+        let mut len = label.len() as libc::socklen_t;
+        // SAFETY: `label` is valid for writes of `len` bytes.
+        let ret = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERSEC,
+                label.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if ret == 0 {
+            label.truncate(len as usize);
+            // The kernel may or may not include the trailing NUL byte but D-Bus always has it, as
+            // documented for `ConnectionCredentials::linux_security_label`.
+            if label.last() != Some(&0) {
+                label.push(0);
+            }
+
+            return Ok(label);
+        }
+
+        let err = io::Error::last_os_error();
+        // The buffer is too small and `len` now holds the required size.
+        if err.raw_os_error() == Some(libc::ERANGE) && len as usize > label.len() {
+            label.resize(len as usize, 0);
+            continue;
+        }
+
+        return Err(err);
+    }
+}
+
 // Send 0 byte as a separate SCM_CREDS message.
 #[cfg(any(target_os = "freebsd", target_os = "dragonfly"))]
 async fn send_zero_byte(fd: &impl AsRawFd) -> io::Result<usize> {
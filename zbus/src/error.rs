@@ -4,8 +4,10 @@ use zbus_names::{Error as NamesError, InterfaceName, OwnedErrorName};
 use zvariant::{Error as VariantError, ObjectPath};
 
 use crate::{
+    conn::AuthMechanism,
     fdo,
     message::{Message, Type},
+    OwnedGuid,
 };
 
 /// The error type for `zbus`.
@@ -33,6 +35,16 @@ pub enum Error {
     IncorrectEndian,
     /// Initial handshake error.
     Handshake(String),
+    /// The server rejected the authentication mechanism we tried.
+    ///
+    /// Contains the rejected mechanism and the mechanisms the server advertised as accepted, as
+    /// sent by the server.
+    AuthRejected(AuthMechanism, Vec<String>),
+    /// The server GUID didn't match the expected one.
+    ///
+    /// Contains the expected and the actual GUID. The server having been restarted since the
+    /// expected GUID was obtained (e.g. from the address) is a likely reason.
+    GuidMismatch(OwnedGuid, OwnedGuid),
     /// Unexpected or incorrect reply.
     InvalidReply,
     /// A D-Bus method error reply.
@@ -71,6 +83,8 @@ impl PartialEq for Error {
             (Self::Address(_), Self::Address(_)) => true,
             (Self::InterfaceNotFound, Self::InterfaceNotFound) => true,
             (Self::Handshake(_), Self::Handshake(_)) => true,
+            (Self::AuthRejected(m1, a1), Self::AuthRejected(m2, a2)) => m1 == m2 && a1 == a2,
+            (Self::GuidMismatch(e1, a1), Self::GuidMismatch(e2, a2)) => e1 == e2 && a1 == a2,
             (Self::InvalidReply, Self::InvalidReply) => true,
             (Self::ExcessData, Self::ExcessData) => true,
             (Self::IncorrectEndian, Self::IncorrectEndian) => true,
@@ -101,6 +115,8 @@ impl error::Error for Error {
             Error::InputOutput(e) => Some(e),
             Error::ExcessData => None,
             Error::Handshake(_) => None,
+            Error::AuthRejected(_, _) => None,
+            Error::GuidMismatch(_, _) => None,
             Error::IncorrectEndian => None,
             Error::Variant(e) => Some(e),
             Error::Names(e) => Some(e),
@@ -129,6 +145,15 @@ impl fmt::Display for Error {
             Error::ExcessData => write!(f, "excess data"),
             Error::InputOutput(e) => write!(f, "I/O error: {e}"),
             Error::Handshake(e) => write!(f, "D-Bus handshake failed: {e}"),
+            Error::AuthRejected(mechanism, accepted) => write!(
+                f,
+                "D-Bus handshake failed: {mechanism} rejected by the server. Accepted mechanisms: [{}]",
+                accepted.join(", ")
+            ),
+            Error::GuidMismatch(expected, actual) => write!(
+                f,
+                "D-Bus handshake failed: Server GUID mismatch: expected {expected}, got {actual}"
+            ),
             Error::IncorrectEndian => write!(f, "incorrect endian"),
             Error::InvalidField => write!(f, "invalid message field"),
             Error::Variant(e) => write!(f, "{e}"),
@@ -164,6 +189,8 @@ impl Clone for Error {
             Error::ExcessData => Error::ExcessData,
             Error::InputOutput(e) => Error::InputOutput(e.clone()),
             Error::Handshake(e) => Error::Handshake(e.clone()),
+            Error::AuthRejected(m, a) => Error::AuthRejected(*m, a.clone()),
+            Error::GuidMismatch(e, a) => Error::GuidMismatch(e.clone(), a.clone()),
             Error::IncorrectEndian => Error::IncorrectEndian,
             Error::InvalidField => Error::InvalidField,
             Error::Variant(e) => Error::Variant(e.clone()),
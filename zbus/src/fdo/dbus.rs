@@ -117,7 +117,7 @@ assert_impl_all!(ReleaseNameReply: Send, Sync, Unpin);
 ///
 /// **Note**: unknown keys, in particular those with "." that are not from the specification, will
 /// be ignored. Use your own implementation or contribute your keys here, or in the specification.
-#[derive(Debug, Default, DeserializeDict, PartialEq, Eq, SerializeDict, Type)]
+#[derive(Clone, Debug, Default, DeserializeDict, PartialEq, Eq, SerializeDict, Type)]
 #[zvariant(signature = "a{sv}")]
 pub struct ConnectionCredentials {
     #[zvariant(rename = "UnixUserID")]
@@ -142,6 +142,16 @@ impl ConnectionCredentials {
         self.unix_user_id
     }
 
+    /// Whether these credentials belong to the same user as the current process.
+    ///
+    /// The Unix user ID is compared against the effective UID of the current process, since that's
+    /// what the kernel reports as the user of a Unix socket peer. If the user ID is unknown, this
+    /// returns `false`.
+    #[cfg(unix)]
+    pub fn is_same_user_as_current(&self) -> bool {
+        self.unix_user_id == Some(nix::unistd::Uid::effective().as_raw())
+    }
+
     /// The numeric Unix group IDs (including both the primary group and the supplementary groups),
     /// as defined by POSIX, in numerically sorted order. This array is either complete or absent:
     /// if the message bus is able to determine some but not all of the caller's groups, or if one
@@ -2,6 +2,7 @@ use std::{
     borrow::{Borrow, Cow},
     fmt::{self, Debug, Display, Formatter},
     ops::Deref,
+    path::Path,
     str::FromStr,
 };
 
@@ -41,6 +42,62 @@ impl Guid<'_> {
         Guid(s.into())
     }
 
+    /// Generate a D-Bus GUID using the given random number generator.
+    ///
+    /// Unlike [`Guid::generate`], all of the GUID is drawn from `rng`, so the result only depends
+    /// on the state of `rng`. This is useful to get reproducible GUIDs in tests, or to use a
+    /// specific source of entropy.
+    ///
+    /// This method is only available when the `p2p` feature is enabled (disabled by default).
+    #[cfg(feature = "p2p")]
+    pub fn generate_with<R: rand::RngCore + ?Sized>(rng: &mut R) -> Guid<'static> {
+        let s = format!(
+            "{:08x}{:08x}{:08x}{:08x}",
+            rng.next_u32(),
+            rng.next_u32(),
+            rng.next_u32(),
+            rng.next_u32()
+        );
+        Guid(s.into())
+    }
+
+    /// Create a GUID from the machine ID.
+    ///
+    /// The machine ID is read from `/var/lib/dbus/machine-id`, falling back to `/etc/machine-id`.
+    /// In contrast to [`Guid::generate`], this provides a GUID that is stable for the machine.
+    ///
+    /// Returns `Err(`[`Error::InputOutput`]`)` if neither file could be read, or
+    /// `Err(`[`Error::InvalidGUID`]`)` if the machine ID is not a well-formed GUID.
+    ///
+    /// [`Error::InputOutput`]: enum.Error.html#variant.InputOutput
+    /// [`Error::InvalidGUID`]: enum.Error.html#variant.InvalidGUID
+    pub fn from_machine_id() -> crate::Result<Guid<'static>> {
+        Self::from_machine_id_file("/var/lib/dbus/machine-id")
+            .or_else(|_| Self::from_machine_id_file("/etc/machine-id"))
+    }
+
+    fn from_machine_id_file<P: AsRef<Path>>(path: P) -> crate::Result<Guid<'static>> {
+        let id = std::fs::read_to_string(path)?;
+
+        Guid::try_from(id.trim_end().to_owned())
+    }
+
+    /// Create a GUID from a string with 32 lowercase hex digits.
+    ///
+    /// Unlike the `TryFrom` implementations, which accept uppercase hex digits and convert them to
+    /// lowercase, this rejects uppercase hex digits as the specification requires.
+    ///
+    /// Returns `Err(`[`Error::InvalidGUID`]`)` if the provided string is not a well-formed GUID.
+    ///
+    /// [`Error::InvalidGUID`]: enum.Error.html#variant.InvalidGUID
+    pub fn try_from_strict(value: &str) -> crate::Result<Guid<'_>> {
+        if value.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err(crate::Error::InvalidGUID);
+        }
+
+        Guid::try_from(value)
+    }
+
     /// Return a string slice for the GUID.
     pub fn as_str(&self) -> &str {
         self.0.as_str()
@@ -76,7 +133,7 @@ impl<'g> TryFrom<&'g str> for Guid<'g> {
     fn try_from(value: &'g str) -> std::result::Result<Self, Self::Error> {
         validate_guid(value)?;
 
-        Ok(Self(Str::from(value)))
+        Ok(Self(to_lowercase(Str::from(value))))
     }
 }
 
@@ -91,7 +148,7 @@ impl<'g> TryFrom<Str<'g>> for Guid<'g> {
     fn try_from(value: Str<'g>) -> std::result::Result<Self, Self::Error> {
         validate_guid(&value)?;
 
-        Ok(Guid(value))
+        Ok(Guid(to_lowercase(value)))
     }
 }
 
@@ -101,7 +158,7 @@ impl TryFrom<String> for Guid<'_> {
     fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
         validate_guid(&value)?;
 
-        Ok(Guid(value.into()))
+        Ok(Guid(to_lowercase(value.into())))
     }
 }
 
@@ -111,7 +168,7 @@ impl<'g> TryFrom<Cow<'g, str>> for Guid<'g> {
     fn try_from(value: Cow<'g, str>) -> std::result::Result<Self, Self::Error> {
         validate_guid(&value)?;
 
-        Ok(Guid(value.into()))
+        Ok(Guid(to_lowercase(value.into())))
     }
 }
 
@@ -141,6 +198,15 @@ fn validate_guid(value: &str) -> crate::Result<()> {
     Ok(())
 }
 
+// The spec mandates lowercase hex digits but some servers send uppercase ones.
+fn to_lowercase(value: Str<'_>) -> Str<'_> {
+    if value.bytes().any(|b| b.is_ascii_uppercase()) {
+        Str::from(value.as_str().to_ascii_lowercase())
+    } else {
+        value
+    }
+}
+
 impl From<Guid<'_>> for String {
     fn from(guid: Guid<'_>) -> Self {
         guid.0.into()
@@ -248,11 +314,11 @@ impl Display for OwnedGuid {
 }
 
 #[cfg(test)]
-#[cfg(feature = "p2p")]
 mod tests {
     use crate::Guid;
     use test_log::test;
 
+    #[cfg(feature = "p2p")]
     #[test]
     fn generate() {
         let u1 = Guid::generate();
@@ -262,4 +328,53 @@ mod tests {
         assert_ne!(u1, u2);
         assert_ne!(u1.as_str(), u2.as_str());
     }
+
+    #[cfg(feature = "p2p")]
+    #[test]
+    fn generate_with() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let u1 = Guid::generate_with(&mut StdRng::seed_from_u64(42));
+        let u2 = Guid::generate_with(&mut StdRng::seed_from_u64(42));
+        assert_eq!(u1, u2);
+        assert!(Guid::try_from(u1.as_str()).is_ok());
+        assert_ne!(u1, Guid::generate_with(&mut StdRng::seed_from_u64(43)));
+    }
+
+    #[test]
+    fn uppercase() {
+        let guid = Guid::try_from("D6F0D8A7AE4E4B5A9E0C0D1B6E3F1A2C").unwrap();
+        assert_eq!(guid.to_string(), "d6f0d8a7ae4e4b5a9e0c0d1b6e3f1a2c");
+        assert_eq!(
+            guid,
+            Guid::try_from("d6f0d8a7ae4e4b5a9e0c0d1b6e3f1a2c").unwrap()
+        );
+
+        assert_eq!(
+            Guid::try_from_strict("D6F0D8A7AE4E4B5A9E0C0D1B6E3F1A2C").unwrap_err(),
+            crate::Error::InvalidGUID
+        );
+        assert!(Guid::try_from_strict("d6f0d8a7ae4e4b5a9e0c0d1b6e3f1a2c").is_ok());
+    }
+
+    #[test]
+    fn from_machine_id_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("machine-id");
+
+        std::fs::write(&path, "d6f0d8a7ae4e4b5a9e0c0d1b6e3f1a2c\n").unwrap();
+        let guid = Guid::from_machine_id_file(&path).unwrap();
+        assert_eq!(guid.as_str(), "d6f0d8a7ae4e4b5a9e0c0d1b6e3f1a2c");
+
+        std::fs::write(&path, "not-a-machine-id\n").unwrap();
+        assert_eq!(
+            Guid::from_machine_id_file(&path).unwrap_err(),
+            crate::Error::InvalidGUID
+        );
+
+        assert!(matches!(
+            Guid::from_machine_id_file(dir.path().join("missing")).unwrap_err(),
+            crate::Error::InputOutput(_)
+        ));
+    }
 }
@@ -1,6 +1,12 @@
 #[cfg(unix)]
 pub(crate) const FDS_MAX: usize = 1024; // this is hardcoded in sdbus - nothing in the spec
 
+// The max number of FDs Linux accepts in a single `sendmsg` call (`SCM_MAX_FD`). Other platforms
+// have their own limits, which we don't query, so this Linux-derived value is used as a conservative
+// limit on every Unix platform.
+#[cfg(unix)]
+pub(crate) const SCM_MAX_FD: usize = 253;
+
 pub(crate) fn padding_for_8_bytes(value: usize) -> usize {
     padding_for_n_bytes(value, 8)
 }
@@ -52,6 +58,30 @@ pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
         .block_on(future)
 }
 
+/// Run `future`, giving up if it doesn't complete within `timeout`.
+///
+/// Returns `None` on timeout.
+pub(crate) async fn timeout<F, T>(future: F, timeout: std::time::Duration) -> Option<T>
+where
+    F: std::future::Future<Output = T>,
+{
+    #[cfg(not(feature = "tokio"))]
+    {
+        use futures_util::future::{select, Either};
+
+        futures_util::pin_mut!(future);
+        match select(future, async_io::Timer::after(timeout)).await {
+            Either::Left((res, _)) => Some(res),
+            Either::Right(_) => None,
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    {
+        tokio::time::timeout(timeout, future).await.ok()
+    }
+}
+
 // If we're running inside a Flatpak sandbox.
 pub(crate) fn is_flatpak() -> bool {
     std::env::var("FLATPAK_ID").is_ok()